@@ -0,0 +1,219 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoders turn what is read from serial port into points and log messages
+
+mod arduino;
+pub(crate) mod binary;
+mod csv;
+mod json;
+mod mavlink;
+pub(crate) mod mqtt;
+mod nmea;
+
+use crate::util::{self, DecoderKind, MessageSerialThread, Properties};
+
+/// Turns input of serial port into messages for graph and log
+///
+/// Text decoders get one line at a time without line ending. Binary decoders
+/// get raw bytes as they arrive and have to find frames themselves.
+pub(crate) trait Decoder: Send {
+    /// Decode a single line of text
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread>;
+
+    /// Decode raw bytes, only called if `is_binary` is true
+    fn decode_bytes(&mut self, _bytes: &[u8]) -> Vec<MessageSerialThread> {
+        Vec::new()
+    }
+
+    /// Whether decoder wants raw bytes instead of lines
+    fn is_binary(&self) -> bool {
+        false
+    }
+}
+
+/// Makes decoder selected in config
+pub(crate) fn new_decoder(config: &Properties) -> Box<dyn Decoder> {
+    let kind = match config.decoder.lock() {
+        Ok(a) => a.to_owned(),
+        Err(_) => DecoderKind::Tarangam,
+    };
+
+    match kind {
+        DecoderKind::Tarangam => Box::new(TarangamDecoder),
+        DecoderKind::Arduino => Box::new(arduino::ArduinoDecoder::new()),
+        DecoderKind::Csv => Box::new(csv::CsvDecoder::new(match config.csv_delimiter.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => ',',
+        })),
+        DecoderKind::Json => Box::new(json::JsonDecoder),
+        DecoderKind::Binary => Box::new(binary::BinaryDecoder::new(
+            match config.binary_format.lock() {
+                Ok(a) => a.clone(),
+                Err(_) => binary::BinaryFormat::default(),
+            },
+        )),
+        DecoderKind::Mavlink => Box::new(mavlink::MavlinkDecoder::new()),
+        DecoderKind::Nmea => Box::new(nmea::NmeaDecoder),
+    }
+}
+
+/// Decoder for lines like `#a=1 b=2` or `#1 5 4`, lines without `#` are log
+pub(crate) struct TarangamDecoder;
+
+impl Decoder for TarangamDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        if !line.starts_with("#") {
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        let mut msgs = Vec::new();
+        let mut points: Vec<(String, f64)> = Vec::new();
+        let mut offset = 1; // byte offset of token, after `#`
+        for (index, token) in line[1..].split(" ").enumerate() {
+            let column = line[..offset].chars().count() + 1;
+            offset += token.len() + 1;
+            if token.trim().is_empty() {
+                continue;
+            }
+
+            let point = match token.split_once("=") {
+                None => parse_value(token).map(|val| (index.to_string(), val)),
+                Some((name, _)) if name.trim().is_empty() => Err("Missing name".to_owned()),
+                Some((name, value)) if value.trim().is_empty() => {
+                    Err(format!("Missing value of {}", name.trim()))
+                }
+                Some((_, value)) if value.contains("=") => Err("Too many =".to_owned()),
+                Some((name, value)) => parse_value(value).map(|val| (name.trim().to_owned(), val)),
+            };
+            match point {
+                Ok(point) => points.push(point),
+                Err(reason) => msgs.push(MessageSerialThread::ParseError(util::ParseError {
+                    line: line.to_owned(),
+                    column,
+                    reason,
+                })),
+            }
+        }
+
+        msgs.push(MessageSerialThread::Points(None, points));
+        msgs.push(MessageSerialThread::Msg(
+            line.to_owned(),
+            util::MessageSerialThreadMsgType::Point,
+        ));
+        msgs
+    }
+}
+
+/// Value of a point, which must be a finite number
+fn parse_value(text: &str) -> Result<f64, String> {
+    match text.trim().parse::<f64>() {
+        Ok(val) if val.is_finite() => Ok(val),
+        Ok(_) => Err(format!("{} is not a finite number", text.trim())),
+        Err(_) => Err(format!("{} is not a number", text.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points of all messages, for tests of every decoder
+    pub(crate) fn points(msgs: Vec<MessageSerialThread>) -> Vec<(String, f64)> {
+        let mut points = Vec::new();
+        for msg in msgs {
+            if let MessageSerialThread::Points(_, p) = msg {
+                points.extend(p);
+            }
+        }
+        points
+    }
+
+    /// Points, and columns with reasons of parse errors
+    type Decoded = (Vec<(String, f64)>, Vec<(usize, String)>);
+
+    /// Points and parse errors of a decoded line
+    fn decode(line: &str) -> Decoded {
+        let mut points = Vec::new();
+        let mut errors = Vec::new();
+        for msg in TarangamDecoder.decode_line(line) {
+            match msg {
+                MessageSerialThread::Points(_, p) => points.extend(p),
+                MessageSerialThread::ParseError(e) => errors.push((e.column, e.reason)),
+                _ => {}
+            }
+        }
+        (points, errors)
+    }
+
+    #[test]
+    fn named_points() {
+        let (points, errors) = decode("#a=1 b=2.5");
+        assert_eq!(points, vec![("a".to_owned(), 1.0), ("b".to_owned(), 2.5)]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn unnamed_points_use_index() {
+        let (points, _) = decode("#1 5 4");
+        assert_eq!(
+            points,
+            vec![
+                ("0".to_owned(), 1.0),
+                ("1".to_owned(), 5.0),
+                ("2".to_owned(), 4.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn line_without_hash_is_log() {
+        let msgs = TarangamDecoder.decode_line("hello");
+        assert!(matches!(
+            msgs.as_slice(),
+            [MessageSerialThread::Msg(
+                _,
+                util::MessageSerialThreadMsgType::Log
+            )]
+        ));
+    }
+
+    #[test]
+    fn bad_tokens_are_reported_with_column() {
+        let (points, errors) = decode("#a=1 b=x =3 c= d=1=2 e=inf");
+        assert_eq!(points, vec![("a".to_owned(), 1.0)]);
+        assert_eq!(
+            errors,
+            vec![
+                (6, "x is not a number".to_owned()),
+                (10, "Missing name".to_owned()),
+                (13, "Missing value of c".to_owned()),
+                (16, "Too many =".to_owned()),
+                (22, "inf is not a finite number".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn column_counts_characters() {
+        let (_, errors) = decode("#ä=1 b=x");
+        assert_eq!(errors, vec![(6, "x is not a number".to_owned())]);
+    }
+}
@@ -0,0 +1,224 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for grammar of Arduino IDE Serial Plotter
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// Decoder for lines like `1,2,3`, `1 2 3` or `temp:24.5,hum:40`, values may also be
+/// separated by tabs
+///
+/// A line having only labels (like `temp,hum`) names the values of following
+/// lines which don't have their own label, if the line after it has as many values.
+/// So text like `WiFi connected` doesn't rename values.
+pub(crate) struct ArduinoDecoder {
+    labels: Vec<String>,
+    header: Option<Vec<String>>, // line of labels, till next line shows it is a header
+}
+
+impl ArduinoDecoder {
+    pub(crate) fn new() -> Self {
+        ArduinoDecoder {
+            labels: Vec::new(),
+            header: None,
+        }
+    }
+}
+
+/// Values of line, a label and its value like `temp: 24.5` stay one token
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    for token in line
+        .split([',', '\t', ' '])
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+    {
+        match tokens.last_mut() {
+            Some(last) if last.ends_with(':') => last.push_str(token),
+            _ => tokens.push(token.to_owned()),
+        }
+    }
+    tokens
+}
+
+impl Decoder for ArduinoDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        let tokens = tokens(line);
+
+        // may be header line, only labels
+        if !tokens.is_empty()
+            && tokens
+                .iter()
+                .all(|token| !token.contains(':') && token.parse::<f64>().is_err())
+        {
+            self.header = Some(tokens);
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+        if let Some(header) = self.header.take() {
+            if header.len() == tokens.len() {
+                self.labels = header;
+            }
+        }
+
+        let mut points: Vec<(String, f64)> = Vec::new();
+        for (index, token) in tokens.iter().enumerate() {
+            match token.split_once(':') {
+                Some((label, value)) => {
+                    if let Ok(num) = value.trim().parse::<f64>() {
+                        let label = label.trim();
+                        if label.is_empty() {
+                            points.push((self.label(index), num));
+                        } else {
+                            points.push((label.to_owned(), num));
+                        }
+                    }
+                }
+                None => {
+                    if let Ok(num) = token.parse::<f64>() {
+                        points.push((self.label(index), num));
+                    }
+                }
+            }
+        }
+
+        if points.is_empty() {
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        vec![
+            MessageSerialThread::Points(None, points),
+            MessageSerialThread::Msg(line.to_owned(), util::MessageSerialThreadMsgType::Point),
+        ]
+    }
+}
+
+impl ArduinoDecoder {
+    /// label from header line or index of value
+    fn label(&self, index: usize) -> String {
+        match self.labels.get(index) {
+            Some(label) => label.to_owned(),
+            None => index.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    #[test]
+    fn separators() {
+        let mut decoder = ArduinoDecoder::new();
+        for line in ["1,2,3", "1 2 3", "1\t2\t3", "1, 2,\t3"] {
+            assert_eq!(
+                points(decoder.decode_line(line)),
+                vec![
+                    ("0".to_owned(), 1.0),
+                    ("1".to_owned(), 2.0),
+                    ("2".to_owned(), 3.0)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let mut decoder = ArduinoDecoder::new();
+        assert_eq!(
+            points(decoder.decode_line("temp:24.5,hum:40")),
+            vec![("temp".to_owned(), 24.5), ("hum".to_owned(), 40.0)]
+        );
+    }
+
+    #[test]
+    fn header_names_following_values() {
+        let mut decoder = ArduinoDecoder::new();
+        assert!(points(decoder.decode_line("temp,hum")).is_empty());
+        assert_eq!(
+            points(decoder.decode_line("24.5 40")),
+            vec![("temp".to_owned(), 24.5), ("hum".to_owned(), 40.0)]
+        );
+        assert_eq!(
+            points(decoder.decode_line("24.5 40 7")),
+            vec![
+                ("temp".to_owned(), 24.5),
+                ("hum".to_owned(), 40.0),
+                ("2".to_owned(), 7.0)
+            ]
+        );
+        assert_eq!(
+            points(decoder.decode_line("1,light:3")),
+            vec![("temp".to_owned(), 1.0), ("light".to_owned(), 3.0)]
+        );
+    }
+
+    #[test]
+    fn text_is_not_header() {
+        let mut decoder = ArduinoDecoder::new();
+        decoder.decode_line("temp,hum");
+        decoder.decode_line("1,2");
+        assert!(points(decoder.decode_line("WiFi connected now")).is_empty());
+        assert_eq!(
+            points(decoder.decode_line("3,4")),
+            vec![("temp".to_owned(), 3.0), ("hum".to_owned(), 4.0)]
+        );
+        // text just before as many values is taken as header
+        decoder.decode_line("Booting ready");
+        assert_eq!(
+            points(decoder.decode_line("5 6")),
+            vec![("Booting".to_owned(), 5.0), ("ready".to_owned(), 6.0)]
+        );
+    }
+
+    #[test]
+    fn label_and_value_apart() {
+        let mut decoder = ArduinoDecoder::new();
+        assert_eq!(
+            points(decoder.decode_line("temp: 24.5")),
+            vec![("temp".to_owned(), 24.5)]
+        );
+        assert_eq!(
+            points(decoder.decode_line("temp: 24.5 hum: 40, light:3")),
+            vec![
+                ("temp".to_owned(), 24.5),
+                ("hum".to_owned(), 40.0),
+                ("light".to_owned(), 3.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn text_is_log() {
+        let mut decoder = ArduinoDecoder::new();
+        let msgs = decoder.decode_line("");
+        assert!(matches!(
+            msgs.as_slice(),
+            [MessageSerialThread::Msg(
+                _,
+                util::MessageSerialThreadMsgType::Log
+            )]
+        ));
+    }
+}
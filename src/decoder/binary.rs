@@ -0,0 +1,548 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for binary frames having COBS or SLIP framing, CRC and layout declared by user
+
+use std::time::{Duration, Instant};
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// How frames are separated in stream
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Framing {
+    Cobs, // frames end with 0x00
+    Slip, // frames end with 0xC0
+}
+
+/// CRC at the end of every frame
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Crc {
+    None,
+    Smbus8,   // poly 0x07, init 0x00
+    Maxim8,   // poly 0x31 reflected, init 0x00 (Dallas 1-Wire)
+    Ccitt16,  // poly 0x1021, init 0xFFFF (CCITT-FALSE)
+    Xmodem16, // poly 0x1021, init 0x0000
+    Modbus16, // poly 0x8005 reflected, init 0xFFFF
+}
+
+/// Type of a field in frame
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+/// Everything needed to decode a frame
+#[derive(Debug, Clone)]
+pub(crate) struct BinaryFormat {
+    pub(crate) framing: Framing,
+    pub(crate) crc: Crc,
+    pub(crate) big_endian: bool,
+    pub(crate) fields: Vec<(String, FieldType)>,
+}
+
+impl Framing {
+    /// Get framing from id of item in combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "cobs" => Some(Framing::Cobs),
+            "slip" => Some(Framing::Slip),
+            _ => None,
+        }
+    }
+}
+
+impl Crc {
+    /// Get crc from id of item in combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "none" => Some(Crc::None),
+            "crc8" => Some(Crc::Smbus8),
+            "crc8_maxim" => Some(Crc::Maxim8),
+            "crc16_ccitt" => Some(Crc::Ccitt16),
+            "crc16_xmodem" => Some(Crc::Xmodem16),
+            "crc16_modbus" => Some(Crc::Modbus16),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes of crc in frame
+    fn size(&self) -> usize {
+        match self {
+            Crc::None => 0,
+            Crc::Smbus8 | Crc::Maxim8 => 1,
+            _ => 2,
+        }
+    }
+
+    fn compute(&self, data: &[u8]) -> u16 {
+        match self {
+            Crc::None => 0,
+            Crc::Smbus8 => {
+                let mut crc = 0u8;
+                for byte in data {
+                    crc ^= byte;
+                    for _ in 0..8 {
+                        crc = if crc & 0x80 != 0 {
+                            (crc << 1) ^ 0x07
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                crc as u16
+            }
+            Crc::Maxim8 => {
+                let mut crc = 0u8;
+                for byte in data {
+                    crc ^= byte;
+                    for _ in 0..8 {
+                        crc = if crc & 0x01 != 0 {
+                            (crc >> 1) ^ 0x8C
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                crc as u16
+            }
+            Crc::Ccitt16 | Crc::Xmodem16 => {
+                let mut crc = if let Crc::Ccitt16 = self {
+                    0xFFFF
+                } else {
+                    0x0000
+                };
+                for byte in data {
+                    crc ^= (*byte as u16) << 8;
+                    for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                            (crc << 1) ^ 0x1021
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                crc
+            }
+            Crc::Modbus16 => {
+                let mut crc = 0xFFFFu16;
+                for byte in data {
+                    crc ^= *byte as u16;
+                    for _ in 0..8 {
+                        crc = if crc & 0x0001 != 0 {
+                            (crc >> 1) ^ 0xA001
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                crc
+            }
+        }
+    }
+}
+
+impl FieldType {
+    fn size(&self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::F64 => 8,
+        }
+    }
+
+    /// Read value from bytes, bytes are as long as size of type
+    fn read(&self, bytes: &[u8], big_endian: bool) -> f64 {
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        if big_endian {
+            raw[..bytes.len()].reverse();
+        }
+        match self {
+            FieldType::U8 => raw[0] as f64,
+            FieldType::I8 => raw[0] as i8 as f64,
+            FieldType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            FieldType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            FieldType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            FieldType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            FieldType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            FieldType::F64 => f64::from_le_bytes(raw),
+        }
+    }
+}
+
+impl BinaryFormat {
+    pub(crate) fn default() -> Self {
+        BinaryFormat {
+            framing: Framing::Cobs,
+            crc: Crc::None,
+            big_endian: false,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Parse layout like `ax:i16 ay:i16 az:i16 t:u32` into fields
+    pub(crate) fn parse_layout(layout: &str) -> Result<Vec<(String, FieldType)>, String> {
+        let mut fields = Vec::new();
+        for part in layout.split(|c: char| c == ',' || c.is_whitespace()) {
+            if part.is_empty() {
+                continue;
+            }
+            let (name, kind) = match part.split_once(':') {
+                Some(a) => a,
+                None => return Err(format!("Missing type of {}", part)),
+            };
+            let kind = match kind {
+                "u8" => FieldType::U8,
+                "i8" => FieldType::I8,
+                "u16" => FieldType::U16,
+                "i16" => FieldType::I16,
+                "u32" => FieldType::U32,
+                "i32" => FieldType::I32,
+                "f32" => FieldType::F32,
+                "f64" => FieldType::F64,
+                _ => return Err(format!("Unknown type {}", kind)),
+            };
+            fields.push((name.to_owned(), kind));
+        }
+        Ok(fields)
+    }
+
+    /// Number of bytes of a frame after removing framing
+    fn frame_size(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|(_, kind)| kind.size())
+            .sum::<usize>()
+            + self.crc.size()
+    }
+}
+
+/// Decoder for binary frames
+pub(crate) struct BinaryDecoder {
+    format: BinaryFormat,
+    frame: Vec<u8>,
+    max_frame: usize, // longest frame before removing framing, longer is dropped
+    overflow: bool,   // frame was too long, bytes are dropped till next delimiter
+    slip_escape: bool,
+    corrupt: u64,
+    last_report: Instant,
+}
+
+impl BinaryDecoder {
+    pub(crate) fn new(format: BinaryFormat) -> Self {
+        let size = format.frame_size();
+        let max_frame = match format.framing {
+            // COBS adds a byte at start and one in every 254 bytes
+            Framing::Cobs => size + size / 254 + 1,
+            // escapes of SLIP are removed while reading
+            Framing::Slip => size,
+        };
+        BinaryDecoder {
+            format,
+            frame: Vec::new(),
+            max_frame,
+            overflow: false,
+            slip_escape: false,
+            corrupt: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Decode a frame, after removing framing
+    fn decode_frame(&mut self, frame: &[u8], msgs: &mut Vec<MessageSerialThread>) {
+        if frame.len() != self.format.frame_size() {
+            self.mark_corrupt(msgs);
+            return;
+        }
+
+        let (data, crc) = frame.split_at(frame.len() - self.format.crc.size());
+        let expected = match crc.len() {
+            1 => crc[0] as u16,
+            2 if self.format.big_endian => u16::from_be_bytes([crc[0], crc[1]]),
+            2 => u16::from_le_bytes([crc[0], crc[1]]),
+            _ => 0,
+        };
+        if self.format.crc.compute(data) != expected {
+            self.mark_corrupt(msgs);
+            return;
+        }
+
+        let mut points: Vec<(String, f64)> = Vec::new();
+        let mut offset = 0;
+        for (name, kind) in self.format.fields.iter() {
+            let size = kind.size();
+            points.push((
+                name.to_owned(),
+                kind.read(&data[offset..offset + size], self.format.big_endian),
+            ));
+            offset += size;
+        }
+
+        let text = points
+            .iter()
+            .map(|(name, val)| format!("{}={}", name, val))
+            .collect::<Vec<String>>()
+            .join(" ");
+        msgs.push(MessageSerialThread::Points(None, points));
+        msgs.push(MessageSerialThread::Msg(
+            text,
+            util::MessageSerialThreadMsgType::Point,
+        ));
+    }
+
+    /// Add byte to frame, a frame longer than layout is counted as corrupt
+    /// and dropped, so that decoder syncs again at next delimiter
+    fn push(&mut self, byte: u8, msgs: &mut Vec<MessageSerialThread>) {
+        if self.overflow {
+            return;
+        }
+        if self.frame.len() >= self.max_frame {
+            self.frame.clear();
+            self.overflow = true;
+            self.mark_corrupt(msgs);
+            return;
+        }
+        self.frame.push(byte);
+    }
+
+    /// Count corrupt frame and tell status bar, not more than twice a second
+    fn mark_corrupt(&mut self, msgs: &mut Vec<MessageSerialThread>) {
+        self.corrupt += 1;
+        if self.corrupt == 1 || self.last_report.elapsed() > Duration::from_millis(500) {
+            self.last_report = Instant::now();
+            msgs.push(MessageSerialThread::Status(format!(
+                "Corrupt frames: {}",
+                self.corrupt
+            )));
+        }
+    }
+}
+
+impl Decoder for BinaryDecoder {
+    fn decode_line(&mut self, _line: &str) -> Vec<MessageSerialThread> {
+        Vec::new()
+    }
+
+    fn decode_bytes(&mut self, bytes: &[u8]) -> Vec<MessageSerialThread> {
+        let mut msgs = Vec::new();
+        for byte in bytes {
+            match self.format.framing {
+                Framing::Cobs => {
+                    if *byte != 0x00 {
+                        self.push(*byte, &mut msgs);
+                        continue;
+                    }
+                    let frame = std::mem::take(&mut self.frame);
+                    if std::mem::take(&mut self.overflow) || frame.is_empty() {
+                        continue;
+                    }
+                    match cobs_decode(&frame) {
+                        Some(frame) => self.decode_frame(&frame, &mut msgs),
+                        None => self.mark_corrupt(&mut msgs),
+                    }
+                }
+                Framing::Slip => match (*byte, self.slip_escape) {
+                    (0xC0, _) => {
+                        self.slip_escape = false;
+                        let frame = std::mem::take(&mut self.frame);
+                        if !std::mem::take(&mut self.overflow) && !frame.is_empty() {
+                            self.decode_frame(&frame, &mut msgs);
+                        }
+                    }
+                    (0xDB, false) => self.slip_escape = true,
+                    (0xDC, true) => {
+                        self.slip_escape = false;
+                        self.push(0xC0, &mut msgs);
+                    }
+                    (0xDD, true) => {
+                        self.slip_escape = false;
+                        self.push(0xDB, &mut msgs);
+                    }
+                    (byte, escape) => {
+                        // bad escape, push as it is and let crc or size catch it
+                        if escape {
+                            self.slip_escape = false;
+                            self.push(0xDB, &mut msgs);
+                        }
+                        self.push(byte, &mut msgs);
+                    }
+                },
+            }
+        }
+        msgs
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+}
+
+/// Decode COBS encoded frame without the trailing 0x00
+fn cobs_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let code = encoded[i] as usize;
+        if code == 0 || i + code > encoded.len() {
+            return None;
+        }
+        decoded.extend_from_slice(&encoded[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < encoded.len() {
+            decoded.push(0x00);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    fn format(framing: Framing, crc: Crc) -> BinaryFormat {
+        BinaryFormat {
+            framing,
+            crc,
+            big_endian: false,
+            fields: BinaryFormat::parse_layout("a:u8 b:i16").unwrap(),
+        }
+    }
+
+    /// Frame with a=7 b=-2 and crc of it
+    fn frame(crc: Crc) -> Vec<u8> {
+        let mut frame = vec![7, 0xFE, 0xFF];
+        let sum = crc.compute(&frame);
+        match crc.size() {
+            1 => frame.push(sum as u8),
+            2 => frame.extend_from_slice(&sum.to_le_bytes()),
+            _ => {}
+        }
+        frame
+    }
+
+    fn cobs_encode(data: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![0];
+        let mut code_at = 0;
+        for byte in data {
+            if *byte == 0 {
+                encoded[code_at] = (encoded.len() - code_at) as u8;
+                code_at = encoded.len();
+                encoded.push(0);
+            } else {
+                encoded.push(*byte);
+            }
+        }
+        encoded[code_at] = (encoded.len() - code_at) as u8;
+        encoded.push(0x00);
+        encoded
+    }
+
+    fn slip_encode(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for byte in data {
+            match byte {
+                0xC0 => encoded.extend_from_slice(&[0xDB, 0xDC]),
+                0xDB => encoded.extend_from_slice(&[0xDB, 0xDD]),
+                _ => encoded.push(*byte),
+            }
+        }
+        encoded.push(0xC0);
+        encoded
+    }
+
+    fn corrupt(msgs: &[MessageSerialThread]) -> bool {
+        msgs.iter().any(
+            |msg| matches!(msg, MessageSerialThread::Status(text) if text.starts_with("Corrupt")),
+        )
+    }
+
+    #[test]
+    fn crc_check_values() {
+        let data = b"123456789";
+        assert_eq!(Crc::Smbus8.compute(data), 0xF4);
+        assert_eq!(Crc::Maxim8.compute(data), 0xA1);
+        assert_eq!(Crc::Ccitt16.compute(data), 0x29B1);
+        assert_eq!(Crc::Xmodem16.compute(data), 0x31C3);
+        assert_eq!(Crc::Modbus16.compute(data), 0x4B37);
+    }
+
+    #[test]
+    fn cobs_with_crc8() {
+        let mut decoder = BinaryDecoder::new(format(Framing::Cobs, Crc::Smbus8));
+        let bytes = cobs_encode(&frame(Crc::Smbus8));
+        assert_eq!(
+            points(decoder.decode_bytes(&bytes)),
+            vec![("a".to_owned(), 7.0), ("b".to_owned(), -2.0)]
+        );
+    }
+
+    #[test]
+    fn slip_with_crc16_split_across_reads() {
+        let mut decoder = BinaryDecoder::new(format(Framing::Slip, Crc::Modbus16));
+        let mut bytes = slip_encode(&frame(Crc::Modbus16));
+        bytes.extend(slip_encode(&[0xC0, 0xDB, 0x00, 0, 0]));
+        let (first, second) = bytes.split_at(2);
+        assert!(points(decoder.decode_bytes(first)).is_empty());
+        let msgs = decoder.decode_bytes(second);
+        assert!(corrupt(&msgs));
+        assert_eq!(
+            points(msgs),
+            vec![("a".to_owned(), 7.0), ("b".to_owned(), -2.0)]
+        );
+    }
+
+    #[test]
+    fn bad_crc_is_corrupt() {
+        let mut decoder = BinaryDecoder::new(format(Framing::Cobs, Crc::Ccitt16));
+        let mut data = frame(Crc::Ccitt16);
+        data[0] ^= 1;
+        let msgs = decoder.decode_bytes(&cobs_encode(&data));
+        assert!(corrupt(&msgs));
+        assert!(points(msgs).is_empty());
+    }
+
+    #[test]
+    fn long_frame_is_dropped_till_delimiter() {
+        for framing in [Framing::Cobs, Framing::Slip] {
+            let mut decoder = BinaryDecoder::new(format(framing, Crc::Maxim8));
+            let msgs = decoder.decode_bytes(&[0x55; 10_000]);
+            assert!(corrupt(&msgs));
+            assert!(decoder.frame.len() <= decoder.max_frame);
+
+            let data = frame(Crc::Maxim8);
+            let mut bytes = vec![0x55; 100];
+            bytes.extend(match framing {
+                Framing::Cobs => [vec![0x00], cobs_encode(&data)].concat(),
+                Framing::Slip => [vec![0xC0], slip_encode(&data)].concat(),
+            });
+            assert_eq!(
+                points(decoder.decode_bytes(&bytes)),
+                vec![("a".to_owned(), 7.0), ("b".to_owned(), -2.0)]
+            );
+        }
+    }
+}
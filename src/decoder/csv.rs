@@ -0,0 +1,181 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for CSV stream having a header row
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// Decoder for CSV lines, header row names the channels
+///
+/// First non empty row after connecting is the header. Later a row is taken as
+/// header sent again after reset of device only if it has as many cells as the
+/// header, none of them empty or a number, then it renames the channels. Empty
+/// cells are gaps and nothing is plotted for them.
+pub(crate) struct CsvDecoder {
+    delimiter: char,
+    header: Option<Vec<String>>, // None till first row
+}
+
+impl CsvDecoder {
+    pub(crate) fn new(delimiter: char) -> Self {
+        CsvDecoder {
+            delimiter,
+            header: None,
+        }
+    }
+}
+
+impl Decoder for CsvDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        let fields = split_fields(line, self.delimiter);
+
+        let is_header = match &self.header {
+            None => fields.iter().any(|field| !field.is_empty()),
+            Some(header) => {
+                fields.len() == header.len()
+                    && fields
+                        .iter()
+                        .all(|field| !field.is_empty() && field.parse::<f64>().is_err())
+            }
+        };
+        if is_header {
+            self.header = Some(fields);
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        let mut points: Vec<(String, f64)> = Vec::new();
+        for (index, field) in fields.iter().enumerate() {
+            // empty cell is a gap
+            if field.is_empty() {
+                continue;
+            }
+            let num = match field.parse::<f64>() {
+                Ok(val) => val,
+                Err(_) => {
+                    continue;
+                }
+            };
+            let name = match self.header.as_ref().and_then(|header| header.get(index)) {
+                Some(name) if !name.is_empty() => name.to_owned(),
+                _ => index.to_string(),
+            };
+            points.push((name, num));
+        }
+
+        if points.is_empty() {
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        vec![
+            MessageSerialThread::Points(None, points),
+            MessageSerialThread::Msg(line.to_owned(), util::MessageSerialThreadMsgType::Point),
+        ]
+    }
+}
+
+/// Split a CSV line on delimiter. Quoted fields may have delimiter and `""` in them
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if let Some('"') = chars.peek() {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if c == delimiter {
+            fields.push(field.trim().to_owned());
+            field.clear();
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field.trim().to_owned());
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    #[test]
+    fn first_row_is_header() {
+        let mut decoder = CsvDecoder::new(',');
+        assert!(points(decoder.decode_line("")).is_empty());
+        assert!(points(decoder.decode_line("time,temp,,hum")).is_empty());
+        assert_eq!(
+            points(decoder.decode_line("1,24.5,,40,9")),
+            vec![
+                ("time".to_owned(), 1.0),
+                ("temp".to_owned(), 24.5),
+                ("hum".to_owned(), 40.0),
+                ("4".to_owned(), 9.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn text_row_is_not_header() {
+        let mut decoder = CsvDecoder::new(',');
+        decoder.decode_line("a,b");
+        assert!(points(decoder.decode_line("booting")).is_empty());
+        assert!(points(decoder.decode_line("error,,x")).is_empty());
+        assert_eq!(
+            points(decoder.decode_line("1,2")),
+            vec![("a".to_owned(), 1.0), ("b".to_owned(), 2.0)]
+        );
+    }
+
+    #[test]
+    fn header_sent_again_renames() {
+        let mut decoder = CsvDecoder::new(';');
+        decoder.decode_line("a;b");
+        decoder.decode_line("x;y");
+        assert_eq!(
+            points(decoder.decode_line("1;2")),
+            vec![("x".to_owned(), 1.0), ("y".to_owned(), 2.0)]
+        );
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(
+            split_fields(r#"a, "b,c" ,"say ""hi""""#, ','),
+            vec!["a", "b,c", r#"say "hi""#]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for JSON Lines, one JSON object in each line
+
+use serde_json::Value;
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// Decoder for lines like `{"imu":{"ax":0.1,"ay":0.2},"temp":24.5}`
+///
+/// Numbers are plotted with name of nested keys joined by `.`, like `imu.ax`.
+/// Booleans are plotted as 0 or 1 and other fields are sent to log.
+pub(crate) struct JsonDecoder;
+
+impl Decoder for JsonDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        let value = match serde_json::from_str::<Value>(line.trim()) {
+            Ok(value @ Value::Object(_)) | Ok(value @ Value::Array(_)) => value,
+            _ => {
+                return vec![MessageSerialThread::Msg(
+                    line.to_owned(),
+                    util::MessageSerialThreadMsgType::Log,
+                )];
+            }
+        };
+
+        let mut points: Vec<(String, f64)> = Vec::new();
+        let mut texts: Vec<(String, String)> = Vec::new();
+        flatten("", &value, &mut points, &mut texts);
+
+        let mut msgs = Vec::new();
+        if !points.is_empty() {
+            msgs.push(MessageSerialThread::Points(None, points));
+            msgs.push(MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Point,
+            ));
+        }
+        for (key, text) in texts {
+            msgs.push(MessageSerialThread::Msg(
+                format!("{}: {}", key, text),
+                util::MessageSerialThreadMsgType::Log,
+            ));
+        }
+        msgs
+    }
+}
+
+/// Collect numeric leaves of value in points and rest of leaves in texts
+pub(crate) fn flatten(
+    prefix: &str,
+    value: &Value,
+    points: &mut Vec<(String, f64)>,
+    texts: &mut Vec<(String, String)>,
+) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&join(key), value, points, texts);
+            }
+        }
+        Value::Array(list) => {
+            for (index, value) in list.iter().enumerate() {
+                flatten(&join(&index.to_string()), value, points, texts);
+            }
+        }
+        Value::Number(num) => {
+            if let Some(num) = num.as_f64() {
+                points.push((prefix.to_owned(), num));
+            }
+        }
+        Value::Bool(val) => {
+            points.push((prefix.to_owned(), if *val { 1.0 } else { 0.0 }));
+        }
+        Value::String(text) => {
+            texts.push((prefix.to_owned(), text.to_owned()));
+        }
+        Value::Null => {
+            texts.push((prefix.to_owned(), "null".to_owned()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    #[test]
+    fn nested_keys_are_joined() {
+        let mut found = points(
+            JsonDecoder
+                .decode_line(r#"{"imu":{"ax":0.1,"ay":0.2},"temp":24.5,"ok":true,"v":[1,2]}"#),
+        );
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            vec![
+                ("imu.ax".to_owned(), 0.1),
+                ("imu.ay".to_owned(), 0.2),
+                ("ok".to_owned(), 1.0),
+                ("temp".to_owned(), 24.5),
+                ("v.0".to_owned(), 1.0),
+                ("v.1".to_owned(), 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_leaves_are_log() {
+        let msgs = JsonDecoder.decode_line(r#"{"state":"idle","err":null}"#);
+        let mut logs = msgs
+            .iter()
+            .filter_map(|msg| match msg {
+                MessageSerialThread::Msg(text, util::MessageSerialThreadMsgType::Log) => {
+                    Some(text.as_str())
+                }
+                _ => None,
+            })
+            .collect::<Vec<&str>>();
+        logs.sort();
+        assert_eq!(logs, vec!["err: null", "state: idle"]);
+        assert!(points(msgs).is_empty());
+    }
+
+    #[test]
+    fn not_json_is_log() {
+        for line in ["hello", "42", "{broken"] {
+            assert!(matches!(
+                JsonDecoder.decode_line(line).as_slice(),
+                [MessageSerialThread::Msg(
+                    _,
+                    util::MessageSerialThreadMsgType::Log
+                )]
+            ));
+        }
+    }
+}
@@ -0,0 +1,719 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for MAVLink v1 and v2 messages of flight controllers
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// Field as written in message definition: type, name and length of array (0 if not array)
+type FieldDef = (&'static str, &'static str, usize);
+
+/// Message definition: id, name, fields and extension fields, in order of xml definition
+type MessageDef = (u32, &'static str, &'static [FieldDef], &'static [FieldDef]);
+
+/// Messages of common.xml which are decoded, others are only counted
+const MESSAGES: &[MessageDef] = &[
+    (
+        0,
+        "HEARTBEAT",
+        &[
+            ("uint8_t", "type", 0),
+            ("uint8_t", "autopilot", 0),
+            ("uint8_t", "base_mode", 0),
+            ("uint32_t", "custom_mode", 0),
+            ("uint8_t", "system_status", 0),
+            ("uint8_t", "mavlink_version", 0),
+        ],
+        &[],
+    ),
+    (
+        1,
+        "SYS_STATUS",
+        &[
+            ("uint32_t", "onboard_control_sensors_present", 0),
+            ("uint32_t", "onboard_control_sensors_enabled", 0),
+            ("uint32_t", "onboard_control_sensors_health", 0),
+            ("uint16_t", "load", 0),
+            ("uint16_t", "voltage_battery", 0),
+            ("int16_t", "current_battery", 0),
+            ("int8_t", "battery_remaining", 0),
+            ("uint16_t", "drop_rate_comm", 0),
+            ("uint16_t", "errors_comm", 0),
+            ("uint16_t", "errors_count1", 0),
+            ("uint16_t", "errors_count2", 0),
+            ("uint16_t", "errors_count3", 0),
+            ("uint16_t", "errors_count4", 0),
+        ],
+        &[],
+    ),
+    (
+        2,
+        "SYSTEM_TIME",
+        &[
+            ("uint64_t", "time_unix_usec", 0),
+            ("uint32_t", "time_boot_ms", 0),
+        ],
+        &[],
+    ),
+    (
+        24,
+        "GPS_RAW_INT",
+        &[
+            ("uint64_t", "time_usec", 0),
+            ("uint8_t", "fix_type", 0),
+            ("int32_t", "lat", 0),
+            ("int32_t", "lon", 0),
+            ("int32_t", "alt", 0),
+            ("uint16_t", "eph", 0),
+            ("uint16_t", "epv", 0),
+            ("uint16_t", "vel", 0),
+            ("uint16_t", "cog", 0),
+            ("uint8_t", "satellites_visible", 0),
+        ],
+        &[
+            ("int32_t", "alt_ellipsoid", 0),
+            ("uint32_t", "h_acc", 0),
+            ("uint32_t", "v_acc", 0),
+            ("uint32_t", "vel_acc", 0),
+            ("uint32_t", "hdg_acc", 0),
+            ("uint16_t", "yaw", 0),
+        ],
+    ),
+    (
+        26,
+        "SCALED_IMU",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("int16_t", "xacc", 0),
+            ("int16_t", "yacc", 0),
+            ("int16_t", "zacc", 0),
+            ("int16_t", "xgyro", 0),
+            ("int16_t", "ygyro", 0),
+            ("int16_t", "zgyro", 0),
+            ("int16_t", "xmag", 0),
+            ("int16_t", "ymag", 0),
+            ("int16_t", "zmag", 0),
+        ],
+        &[("int16_t", "temperature", 0)],
+    ),
+    (
+        27,
+        "RAW_IMU",
+        &[
+            ("uint64_t", "time_usec", 0),
+            ("int16_t", "xacc", 0),
+            ("int16_t", "yacc", 0),
+            ("int16_t", "zacc", 0),
+            ("int16_t", "xgyro", 0),
+            ("int16_t", "ygyro", 0),
+            ("int16_t", "zgyro", 0),
+            ("int16_t", "xmag", 0),
+            ("int16_t", "ymag", 0),
+            ("int16_t", "zmag", 0),
+        ],
+        &[("uint8_t", "id", 0), ("int16_t", "temperature", 0)],
+    ),
+    (
+        29,
+        "SCALED_PRESSURE",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("float", "press_abs", 0),
+            ("float", "press_diff", 0),
+            ("int16_t", "temperature", 0),
+        ],
+        &[("int16_t", "temperature_press_diff", 0)],
+    ),
+    (
+        30,
+        "ATTITUDE",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("float", "roll", 0),
+            ("float", "pitch", 0),
+            ("float", "yaw", 0),
+            ("float", "rollspeed", 0),
+            ("float", "pitchspeed", 0),
+            ("float", "yawspeed", 0),
+        ],
+        &[],
+    ),
+    (
+        31,
+        "ATTITUDE_QUATERNION",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("float", "q1", 0),
+            ("float", "q2", 0),
+            ("float", "q3", 0),
+            ("float", "q4", 0),
+            ("float", "rollspeed", 0),
+            ("float", "pitchspeed", 0),
+            ("float", "yawspeed", 0),
+        ],
+        &[("float", "repr_offset_q", 4)],
+    ),
+    (
+        32,
+        "LOCAL_POSITION_NED",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("float", "x", 0),
+            ("float", "y", 0),
+            ("float", "z", 0),
+            ("float", "vx", 0),
+            ("float", "vy", 0),
+            ("float", "vz", 0),
+        ],
+        &[],
+    ),
+    (
+        33,
+        "GLOBAL_POSITION_INT",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("int32_t", "lat", 0),
+            ("int32_t", "lon", 0),
+            ("int32_t", "alt", 0),
+            ("int32_t", "relative_alt", 0),
+            ("int16_t", "vx", 0),
+            ("int16_t", "vy", 0),
+            ("int16_t", "vz", 0),
+            ("uint16_t", "hdg", 0),
+        ],
+        &[],
+    ),
+    (
+        35,
+        "RC_CHANNELS_RAW",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("uint8_t", "port", 0),
+            ("uint16_t", "chan1_raw", 0),
+            ("uint16_t", "chan2_raw", 0),
+            ("uint16_t", "chan3_raw", 0),
+            ("uint16_t", "chan4_raw", 0),
+            ("uint16_t", "chan5_raw", 0),
+            ("uint16_t", "chan6_raw", 0),
+            ("uint16_t", "chan7_raw", 0),
+            ("uint16_t", "chan8_raw", 0),
+            ("uint8_t", "rssi", 0),
+        ],
+        &[],
+    ),
+    (
+        36,
+        "SERVO_OUTPUT_RAW",
+        &[
+            ("uint32_t", "time_usec", 0),
+            ("uint8_t", "port", 0),
+            ("uint16_t", "servo1_raw", 0),
+            ("uint16_t", "servo2_raw", 0),
+            ("uint16_t", "servo3_raw", 0),
+            ("uint16_t", "servo4_raw", 0),
+            ("uint16_t", "servo5_raw", 0),
+            ("uint16_t", "servo6_raw", 0),
+            ("uint16_t", "servo7_raw", 0),
+            ("uint16_t", "servo8_raw", 0),
+        ],
+        &[
+            ("uint16_t", "servo9_raw", 0),
+            ("uint16_t", "servo10_raw", 0),
+            ("uint16_t", "servo11_raw", 0),
+            ("uint16_t", "servo12_raw", 0),
+            ("uint16_t", "servo13_raw", 0),
+            ("uint16_t", "servo14_raw", 0),
+            ("uint16_t", "servo15_raw", 0),
+            ("uint16_t", "servo16_raw", 0),
+        ],
+    ),
+    (
+        65,
+        "RC_CHANNELS",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("uint8_t", "chancount", 0),
+            ("uint16_t", "chan1_raw", 0),
+            ("uint16_t", "chan2_raw", 0),
+            ("uint16_t", "chan3_raw", 0),
+            ("uint16_t", "chan4_raw", 0),
+            ("uint16_t", "chan5_raw", 0),
+            ("uint16_t", "chan6_raw", 0),
+            ("uint16_t", "chan7_raw", 0),
+            ("uint16_t", "chan8_raw", 0),
+            ("uint16_t", "chan9_raw", 0),
+            ("uint16_t", "chan10_raw", 0),
+            ("uint16_t", "chan11_raw", 0),
+            ("uint16_t", "chan12_raw", 0),
+            ("uint16_t", "chan13_raw", 0),
+            ("uint16_t", "chan14_raw", 0),
+            ("uint16_t", "chan15_raw", 0),
+            ("uint16_t", "chan16_raw", 0),
+            ("uint16_t", "chan17_raw", 0),
+            ("uint16_t", "chan18_raw", 0),
+            ("uint8_t", "rssi", 0),
+        ],
+        &[],
+    ),
+    (
+        74,
+        "VFR_HUD",
+        &[
+            ("float", "airspeed", 0),
+            ("float", "groundspeed", 0),
+            ("int16_t", "heading", 0),
+            ("uint16_t", "throttle", 0),
+            ("float", "alt", 0),
+            ("float", "climb", 0),
+        ],
+        &[],
+    ),
+    (
+        116,
+        "SCALED_IMU2",
+        &[
+            ("uint32_t", "time_boot_ms", 0),
+            ("int16_t", "xacc", 0),
+            ("int16_t", "yacc", 0),
+            ("int16_t", "zacc", 0),
+            ("int16_t", "xgyro", 0),
+            ("int16_t", "ygyro", 0),
+            ("int16_t", "zgyro", 0),
+            ("int16_t", "xmag", 0),
+            ("int16_t", "ymag", 0),
+            ("int16_t", "zmag", 0),
+        ],
+        &[("int16_t", "temperature", 0)],
+    ),
+    (
+        147,
+        "BATTERY_STATUS",
+        &[
+            ("uint8_t", "id", 0),
+            ("uint8_t", "battery_function", 0),
+            ("uint8_t", "type", 0),
+            ("int16_t", "temperature", 0),
+            ("uint16_t", "voltages", 10),
+            ("int16_t", "current_battery", 0),
+            ("int32_t", "current_consumed", 0),
+            ("int32_t", "energy_consumed", 0),
+            ("int8_t", "battery_remaining", 0),
+        ],
+        &[],
+    ),
+    (
+        241,
+        "VIBRATION",
+        &[
+            ("uint64_t", "time_usec", 0),
+            ("float", "vibration_x", 0),
+            ("float", "vibration_y", 0),
+            ("float", "vibration_z", 0),
+            ("uint32_t", "clipping_0", 0),
+            ("uint32_t", "clipping_1", 0),
+            ("uint32_t", "clipping_2", 0),
+        ],
+        &[],
+    ),
+    (
+        253,
+        "STATUSTEXT",
+        &[("uint8_t", "severity", 0), ("char", "text", 50)],
+        &[],
+    ),
+];
+
+/// Message definition ready to decode payload
+struct MessageInfo {
+    name: &'static str,
+    crc_extra: u8,
+    base_len: usize,
+    fields: Vec<FieldDef>, // in order of wire, extensions at last
+}
+
+/// Decoder for MAVLink v1 and v2
+///
+/// Fields are plotted as `MSG.field` and STATUSTEXT goes to log. Rate of each
+/// message, lost messages and CRC errors are shown in log every second.
+pub(crate) struct MavlinkDecoder {
+    messages: HashMap<u32, MessageInfo>,
+    buf: Vec<u8>,
+    last_seq: HashMap<(u8, u8), u8>,
+    counts: BTreeMap<String, u32>,
+    lost: u32,
+    crc_errors: u32,
+    last_summary: Instant,
+}
+
+impl MavlinkDecoder {
+    pub(crate) fn new() -> Self {
+        let mut messages = HashMap::new();
+        for (id, name, fields, extensions) in MESSAGES {
+            let mut wire = fields.to_vec();
+            // fields are sent sorted by size of type, largest first
+            wire.sort_by_key(|f| std::cmp::Reverse(type_size(f.0)));
+
+            let mut crc = x25_str(0xFFFF, &format!("{} ", name));
+            for (kind, field, len) in wire.iter() {
+                crc = x25_str(crc, &format!("{} ", kind));
+                crc = x25_str(crc, &format!("{} ", field));
+                if *len > 0 {
+                    crc = x25(crc, *len as u8);
+                }
+            }
+
+            let base_len = wire.iter().map(field_size).sum();
+            wire.extend_from_slice(extensions);
+            messages.insert(
+                *id,
+                MessageInfo {
+                    name,
+                    crc_extra: ((crc & 0xFF) ^ (crc >> 8)) as u8,
+                    base_len,
+                    fields: wire,
+                },
+            );
+        }
+
+        MavlinkDecoder {
+            messages,
+            buf: Vec::new(),
+            last_seq: HashMap::new(),
+            counts: BTreeMap::new(),
+            lost: 0,
+            crc_errors: 0,
+            last_summary: Instant::now(),
+        }
+    }
+
+    /// Try to take a packet from start of buffer. Returns None if more bytes are needed
+    fn next_packet(&mut self, msgs: &mut Vec<MessageSerialThread>) -> Option<()> {
+        // skip till start of packet
+        let start = match self.buf.iter().position(|b| *b == 0xFE || *b == 0xFD) {
+            Some(start) => start,
+            None => {
+                self.buf.clear();
+                return None;
+            }
+        };
+        self.buf.drain(..start);
+
+        let v2 = self.buf[0] == 0xFD;
+        let header_len = if v2 { 10 } else { 6 };
+        if self.buf.len() < header_len {
+            return None;
+        }
+        let len = self.buf[1] as usize;
+        let signed = v2 && self.buf[2] & 0x01 != 0;
+        let packet_len = header_len + len + 2 + if signed { 13 } else { 0 };
+        if self.buf.len() < packet_len {
+            return None;
+        }
+
+        let (seq, sysid, compid, msgid) = if v2 {
+            (
+                self.buf[4],
+                self.buf[5],
+                self.buf[6],
+                u32::from_le_bytes([self.buf[7], self.buf[8], self.buf[9], 0]),
+            )
+        } else {
+            (self.buf[2], self.buf[3], self.buf[4], self.buf[5] as u32)
+        };
+
+        let info = match self.messages.get(&msgid) {
+            Some(info) => info,
+            None => {
+                // crc of unknown message can't be checked, so take it as packet
+                // only if next packet starts right after it
+                match self.buf.get(packet_len) {
+                    Some(0xFE) | Some(0xFD) => {
+                        *self.counts.entry(format!("#{}", msgid)).or_insert(0) += 1;
+                        self.buf.drain(..packet_len);
+                    }
+                    Some(_) => {
+                        self.buf.drain(..1);
+                    }
+                    None => return None,
+                }
+                return Some(());
+            }
+        };
+
+        let mut crc = 0xFFFF;
+        for byte in &self.buf[1..header_len + len] {
+            crc = x25(crc, *byte);
+        }
+        crc = x25(crc, info.crc_extra);
+        let crc_got =
+            u16::from_le_bytes([self.buf[header_len + len], self.buf[header_len + len + 1]]);
+        if crc != crc_got || (!v2 && len != info.base_len) {
+            // may be a false start of packet, try again from next byte
+            self.crc_errors += 1;
+            self.buf.drain(..1);
+            return Some(());
+        }
+
+        if let Some(last) = self.last_seq.insert((sysid, compid), seq) {
+            self.lost += seq.wrapping_sub(last).wrapping_sub(1) as u32;
+        }
+        *self.counts.entry(info.name.to_owned()).or_insert(0) += 1;
+
+        // v2 trims zeros at end of payload
+        let mut payload = self.buf[header_len..header_len + len].to_vec();
+        let full_len = info.fields.iter().map(field_size).sum();
+        if payload.len() < full_len {
+            payload.resize(full_len, 0);
+        }
+
+        let mut points: Vec<(String, f64)> = Vec::new();
+        let mut offset = 0;
+        for (kind, field, count) in info.fields.iter() {
+            let size = type_size(kind);
+            if *kind == "char" {
+                let end = offset + std::cmp::max(*count, 1);
+                let text = String::from_utf8_lossy(&payload[offset..end]);
+                msgs.push(MessageSerialThread::Msg(
+                    format!("{}: {}", info.name, text.trim_end_matches('\0')),
+                    util::MessageSerialThreadMsgType::Log,
+                ));
+                offset = end;
+                continue;
+            }
+            if *count == 0 {
+                let val = read_value(kind, &payload[offset..offset + size]);
+                points.push((format!("{}.{}", info.name, field), val));
+                offset += size;
+            } else {
+                for index in 0..*count {
+                    let val = read_value(kind, &payload[offset..offset + size]);
+                    points.push((format!("{}.{}.{}", info.name, field, index), val));
+                    offset += size;
+                }
+            }
+        }
+
+        if !points.is_empty() {
+            let text = format!(
+                "{} {}",
+                info.name,
+                points
+                    .iter()
+                    .map(|(name, val)| format!("{}={}", &name[info.name.len() + 1..], val))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            );
+            msgs.push(MessageSerialThread::Points(None, points));
+            msgs.push(MessageSerialThread::Msg(
+                text,
+                util::MessageSerialThreadMsgType::Point,
+            ));
+        }
+
+        self.buf.drain(..packet_len);
+        Some(())
+    }
+
+    /// Write rate of every message in log, every second
+    fn summary(&mut self, msgs: &mut Vec<MessageSerialThread>) {
+        let elapsed = self.last_summary.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+        self.last_summary = Instant::now();
+
+        let rates = self
+            .counts
+            .iter()
+            .map(|(name, count)| {
+                format!("{} {:.1} Hz", name, *count as f64 / elapsed.as_secs_f64())
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        msgs.push(MessageSerialThread::Msg(
+            format!(
+                "MAVLink: {} | lost {}, crc errors {}",
+                rates, self.lost, self.crc_errors
+            ),
+            util::MessageSerialThreadMsgType::Log,
+        ));
+        if self.lost > 0 || self.crc_errors > 0 {
+            msgs.push(MessageSerialThread::Status(format!(
+                "MAVLink lost {}, crc errors {}",
+                self.lost, self.crc_errors
+            )));
+        }
+        self.counts.clear();
+        self.lost = 0;
+        self.crc_errors = 0;
+    }
+}
+
+impl Decoder for MavlinkDecoder {
+    fn decode_line(&mut self, _line: &str) -> Vec<MessageSerialThread> {
+        Vec::new()
+    }
+
+    fn decode_bytes(&mut self, bytes: &[u8]) -> Vec<MessageSerialThread> {
+        let mut msgs = Vec::new();
+        self.buf.extend_from_slice(bytes);
+        while self.next_packet(&mut msgs).is_some() {}
+        self.summary(&mut msgs);
+        msgs
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+}
+
+/// Size of a type of field
+fn type_size(kind: &str) -> usize {
+    match kind {
+        "uint64_t" | "int64_t" | "double" => 8,
+        "uint32_t" | "int32_t" | "float" => 4,
+        "uint16_t" | "int16_t" => 2,
+        _ => 1,
+    }
+}
+
+/// Size of field with its array
+fn field_size(field: &FieldDef) -> usize {
+    type_size(field.0) * std::cmp::max(field.2, 1)
+}
+
+/// Read little endian value of given type
+fn read_value(kind: &str, bytes: &[u8]) -> f64 {
+    let mut raw = [0u8; 8];
+    raw[..bytes.len()].copy_from_slice(bytes);
+    match kind {
+        "uint8_t" => raw[0] as f64,
+        "int8_t" => raw[0] as i8 as f64,
+        "uint16_t" => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+        "int16_t" => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+        "uint32_t" => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+        "int32_t" => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+        "float" => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+        "uint64_t" => u64::from_le_bytes(raw) as f64,
+        "int64_t" => i64::from_le_bytes(raw) as f64,
+        "double" => f64::from_le_bytes(raw),
+        _ => 0.0,
+    }
+}
+
+/// Accumulate byte in X.25 CRC used by MAVLink
+fn x25(crc: u16, byte: u8) -> u16 {
+    let mut tmp = byte ^ (crc & 0xFF) as u8;
+    tmp ^= tmp << 4;
+    let tmp = tmp as u16;
+    (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+}
+
+fn x25_str(crc: u16, text: &str) -> u16 {
+    text.bytes().fold(crc, x25)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    /// MAVLink v1 or v2 packet of message with id
+    fn packet(decoder: &MavlinkDecoder, v2: bool, seq: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = if v2 {
+            let id = id.to_le_bytes();
+            vec![
+                0xFD,
+                payload.len() as u8,
+                0,
+                0,
+                seq,
+                1,
+                1,
+                id[0],
+                id[1],
+                id[2],
+            ]
+        } else {
+            vec![0xFE, payload.len() as u8, seq, 1, 1, id as u8]
+        };
+        packet.extend_from_slice(payload);
+        let mut crc = packet[1..].iter().fold(0xFFFF, |crc, byte| x25(crc, *byte));
+        crc = x25(crc, decoder.messages[&id].crc_extra);
+        packet.extend_from_slice(&crc.to_le_bytes());
+        packet
+    }
+
+    /// HEARTBEAT with custom_mode 7 and type 2
+    const HEARTBEAT: [u8; 9] = [7, 0, 0, 0, 2, 3, 81, 4, 3];
+
+    #[test]
+    fn crc_extra_of_common_messages() {
+        let decoder = MavlinkDecoder::new();
+        for (id, crc_extra) in [
+            (0, 50),
+            (1, 124),
+            (2, 137),
+            (24, 24),
+            (26, 170),
+            (30, 39),
+            (33, 104),
+            (74, 20),
+            (253, 83),
+        ] {
+            assert_eq!(decoder.messages[&id].crc_extra, crc_extra, "message {}", id);
+        }
+    }
+
+    #[test]
+    fn heartbeat_v1_and_v2() {
+        let mut decoder = MavlinkDecoder::new();
+        for v2 in [false, true] {
+            let bytes = packet(&decoder, v2, 0, 0, &HEARTBEAT);
+            let found = points(decoder.decode_bytes(&bytes));
+            assert!(found.contains(&("HEARTBEAT.custom_mode".to_owned(), 7.0)));
+            assert!(found.contains(&("HEARTBEAT.type".to_owned(), 2.0)));
+        }
+    }
+
+    #[test]
+    fn bad_crc_is_counted() {
+        let mut decoder = MavlinkDecoder::new();
+        let mut bytes = packet(&decoder, false, 0, 0, &HEARTBEAT);
+        bytes[7] ^= 0xFF;
+        assert!(points(decoder.decode_bytes(&bytes)).is_empty());
+        assert_eq!(decoder.crc_errors, 1);
+    }
+
+    #[test]
+    fn sequence_gaps_are_lost_messages() {
+        let mut decoder = MavlinkDecoder::new();
+        let mut bytes = Vec::new();
+        for seq in [254, 255, 0, 4] {
+            // junk before packets is skipped
+            bytes.extend_from_slice(&[0x00, 0x11]);
+            bytes.extend(packet(&decoder, true, seq, 0, &HEARTBEAT));
+        }
+        decoder.decode_bytes(&bytes);
+        assert_eq!(decoder.lost, 3);
+        assert_eq!(decoder.counts.get("HEARTBEAT"), Some(&4));
+    }
+}
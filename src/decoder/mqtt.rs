@@ -0,0 +1,144 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! Decoder for payloads of MQTT messages
+
+use serde_json::Value;
+
+use super::{json, Decoder, TarangamDecoder};
+use crate::util::{self, MessageSerialThread};
+
+/// Decoder for payloads of MQTT messages, topic of message is told by source as its
+/// origin and names the lines
+///
+/// Payload like `24.5` is plotted as a line named after topic, like `home/temp`.
+/// JSON like `{"ax":0.5}` and `#` line like `#ax=0.5` on `home/imu` are plotted as `home/imu/ax`.
+/// Other payloads are sent to log.
+pub(crate) struct MqttDecoder;
+
+impl Decoder for MqttDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        self.decode_bytes(line.as_bytes())
+    }
+
+    fn decode_bytes(&mut self, bytes: &[u8]) -> Vec<MessageSerialThread> {
+        let payload = String::from_utf8_lossy(bytes);
+        let payload = payload.trim();
+        // empty payload only clears retained message of topic
+        if payload.is_empty() {
+            return Vec::new();
+        }
+
+        if let Ok(value) = serde_json::from_str::<Value>(payload) {
+            let mut points: Vec<(String, f64)> = Vec::new();
+            let mut texts: Vec<(String, String)> = Vec::new();
+            // number alone gets no name, so it is named after topic
+            json::flatten("", &value, &mut points, &mut texts);
+
+            let mut msgs = Vec::new();
+            if !points.is_empty() {
+                msgs.push(MessageSerialThread::Points(None, points));
+                msgs.push(MessageSerialThread::Msg(
+                    payload.to_owned(),
+                    util::MessageSerialThreadMsgType::Point,
+                ));
+            }
+            for (key, text) in texts {
+                let text = if key.is_empty() {
+                    text
+                } else {
+                    format!("{}: {}", key, text)
+                };
+                msgs.push(MessageSerialThread::Msg(
+                    text,
+                    util::MessageSerialThreadMsgType::Log,
+                ));
+            }
+            return msgs;
+        }
+
+        if payload.starts_with('#') {
+            return TarangamDecoder.decode_line(payload);
+        }
+
+        vec![MessageSerialThread::Msg(
+            payload.to_owned(),
+            util::MessageSerialThreadMsgType::Log,
+        )]
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    #[test]
+    fn number_is_named_after_topic() {
+        // empty name is given name of topic by stream of topic
+        assert_eq!(
+            points(MqttDecoder.decode_bytes(b" 24.5\n")),
+            vec![("".to_owned(), 24.5)]
+        );
+    }
+
+    #[test]
+    fn json_payload() {
+        let msgs = MqttDecoder.decode_bytes(br#"{"ax":0.5,"imu":{"gy":[1,2]},"state":"ok"}"#);
+        assert_eq!(
+            points(msgs),
+            vec![
+                ("ax".to_owned(), 0.5),
+                ("imu.gy.0".to_owned(), 1.0),
+                ("imu.gy.1".to_owned(), 2.0)
+            ]
+        );
+        let msgs = MqttDecoder.decode_bytes(br#"{"state":"ok"}"#);
+        assert!(matches!(
+            msgs.as_slice(),
+            [MessageSerialThread::Msg(text, util::MessageSerialThreadMsgType::Log)]
+                if text == "state: ok"
+        ));
+    }
+
+    #[test]
+    fn point_line_payload() {
+        assert_eq!(
+            points(MqttDecoder.decode_bytes(b"#a=1 b=2")),
+            vec![("a".to_owned(), 1.0), ("b".to_owned(), 2.0)]
+        );
+    }
+
+    #[test]
+    fn text_payload_is_log() {
+        let msgs = MqttDecoder.decode_bytes(b"booting");
+        assert!(matches!(
+            msgs.as_slice(),
+            [MessageSerialThread::Msg(text, util::MessageSerialThreadMsgType::Log)]
+                if text == "booting"
+        ));
+    }
+
+    #[test]
+    fn empty_payload_is_left() {
+        assert!(MqttDecoder.decode_bytes(b"").is_empty());
+        assert!(MqttDecoder.decode_bytes(b" \n").is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Decoder for NMEA 0183 sentences of GPS modules
+
+use super::Decoder;
+use crate::util::{self, MessageSerialThread};
+
+/// Decoder for sentences like `$GPGGA,...*47`
+///
+/// Fields of GGA, RMC, VTG, GSA, GLL and GSV are plotted as `GGA.alt`,
+/// `RMC.speed` and so on. Sentences with wrong checksum are flagged in log.
+pub(crate) struct NmeaDecoder;
+
+impl Decoder for NmeaDecoder {
+    fn decode_line(&mut self, line: &str) -> Vec<MessageSerialThread> {
+        let line = line.trim();
+        if !line.starts_with('$') || line.len() < 6 {
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        let (body, checksum) = match line[1..].split_once('*') {
+            Some((body, checksum)) => (body, Some(checksum)),
+            None => (&line[1..], None),
+        };
+        if let Some(checksum) = checksum {
+            let computed = body.bytes().fold(0u8, |sum, byte| sum ^ byte);
+            if u8::from_str_radix(checksum, 16).ok() != Some(computed) {
+                return vec![MessageSerialThread::Msg(
+                    format!("Invalid checksum (expected {:02X}): {}", computed, line),
+                    util::MessageSerialThreadMsgType::Log,
+                )];
+            }
+        }
+
+        let fields = body.split(',').collect::<Vec<&str>>();
+        // kind of sentence is taken from end of address, which must be ASCII to be cut
+        if !fields[0].is_ascii() {
+            return vec![
+                MessageSerialThread::ParseError(util::ParseError {
+                    line: line.to_owned(),
+                    column: 2,
+                    reason: format!("Address {} is not ASCII", fields[0]),
+                }),
+                MessageSerialThread::Msg(line.to_owned(), util::MessageSerialThreadMsgType::Log),
+            ];
+        }
+        let points = sentence_points(fields[0], &fields);
+
+        if points.is_empty() {
+            return vec![MessageSerialThread::Msg(
+                line.to_owned(),
+                util::MessageSerialThreadMsgType::Log,
+            )];
+        }
+
+        vec![
+            MessageSerialThread::Points(None, points),
+            MessageSerialThread::Msg(line.to_owned(), util::MessageSerialThreadMsgType::Point),
+        ]
+    }
+}
+
+/// Points of a sentence, address is like `GPGGA` and only has ASCII
+fn sentence_points(address: &str, fields: &[&str]) -> Vec<(String, f64)> {
+    let mut points: Vec<(String, f64)> = Vec::new();
+    if address.len() < 5 {
+        return points;
+    }
+    // talker (GP, GN, GL, ...) is left out, except in GSV where every constellation has its own
+    let kind = &address[address.len() - 3..];
+    let field = |index: usize| fields.get(index).and_then(|f| f.parse::<f64>().ok());
+    let mut push = |name: &str, val: Option<f64>| {
+        if let Some(val) = val {
+            points.push((format!("{}.{}", kind, name), val));
+        }
+    };
+
+    match kind {
+        "GGA" => {
+            push("lat", coordinate(fields, 2));
+            push("lon", coordinate(fields, 4));
+            push("quality", field(6));
+            push("satellites", field(7));
+            push("hdop", field(8));
+            push("alt", field(9));
+            push("geoid", field(11));
+        }
+        "RMC" => {
+            push(
+                "valid",
+                fields.get(2).map(|f| if *f == "A" { 1.0 } else { 0.0 }),
+            );
+            push("lat", coordinate(fields, 3));
+            push("lon", coordinate(fields, 5));
+            push("speed", field(7));
+            push("course", field(8));
+        }
+        "VTG" => {
+            push("course", field(1));
+            push("course_magnetic", field(3));
+            push("speed", field(5));
+            push("speed_kmh", field(7));
+        }
+        "GSA" => {
+            push("fix", field(2));
+            // satellites used in fix are listed in fields 3 to 14
+            let used = (3..15).filter(|i| field(*i).is_some()).count();
+            push("satellites", Some(used as f64));
+            push("pdop", field(15));
+            push("hdop", field(16));
+            push("vdop", field(17));
+        }
+        "GLL" => {
+            push("lat", coordinate(fields, 1));
+            push("lon", coordinate(fields, 3));
+            push(
+                "valid",
+                fields.get(6).map(|f| if *f == "A" { 1.0 } else { 0.0 }),
+            );
+        }
+        "GSV" => {
+            let kind = address;
+            if let Some(val) = field(3) {
+                points.push((format!("{}.in_view", kind), val));
+            }
+            // satellite number, elevation, azimuth and snr for upto 4 satellites
+            let mut index = 4;
+            while index + 3 < fields.len() {
+                if let (Some(prn), Some(snr)) = (field(index), field(index + 3)) {
+                    points.push((format!("{}.snr.{}", kind, prn), snr));
+                }
+                index += 4;
+            }
+        }
+        _ => {}
+    }
+    points
+}
+
+/// Degrees from `ddmm.mmmm` at index and hemisphere after it
+fn coordinate(fields: &[&str], index: usize) -> Option<f64> {
+    let raw = fields.get(index)?.parse::<f64>().ok()?;
+    let degrees = (raw / 100.0).trunc() + (raw % 100.0) / 60.0;
+    match *fields.get(index + 1)? {
+        "S" | "W" => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::points;
+
+    /// Sentence of body with its checksum
+    fn sentence(body: &str) -> String {
+        let sum = body.bytes().fold(0u8, |sum, byte| sum ^ byte);
+        format!("${}*{:02X}", body, sum)
+    }
+
+    fn point(found: &[(String, f64)], name: &str) -> f64 {
+        match found.iter().find(|(n, _)| n == name) {
+            Some((_, val)) => *val,
+            None => panic!("{} missing in {:?}", name, found),
+        }
+    }
+
+    #[test]
+    fn gga_with_checksum() {
+        let line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        let found = points(NmeaDecoder.decode_line(line));
+        assert!((point(&found, "GGA.lat") - (48.0 + 7.038 / 60.0)).abs() < 1e-9);
+        assert!((point(&found, "GGA.lon") - (11.0 + 31.0 / 60.0)).abs() < 1e-9);
+        assert_eq!(point(&found, "GGA.satellites"), 8.0);
+        assert_eq!(point(&found, "GGA.alt"), 545.4);
+    }
+
+    #[test]
+    fn southern_and_western_are_negative() {
+        let line = sentence("GNGLL,3350.000,S,15112.000,W,225444,A,A");
+        let found = points(NmeaDecoder.decode_line(&line));
+        assert!((point(&found, "GLL.lat") + (33.0 + 50.0 / 60.0)).abs() < 1e-9);
+        assert!((point(&found, "GLL.lon") + (151.0 + 12.0 / 60.0)).abs() < 1e-9);
+        assert_eq!(point(&found, "GLL.valid"), 1.0);
+    }
+
+    #[test]
+    fn wrong_checksum_is_log() {
+        let line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*46";
+        match NmeaDecoder.decode_line(line).as_slice() {
+            [MessageSerialThread::Msg(text, util::MessageSerialThreadMsgType::Log)] => {
+                assert!(text.starts_with("Invalid checksum (expected 47)"))
+            }
+            msgs => panic!("unexpected {:?}", msgs),
+        }
+    }
+
+    #[test]
+    fn gsv_keeps_talker() {
+        let line = sentence("GLGSV,1,1,02,65,40,120,33,66,10,200,");
+        assert_eq!(
+            points(NmeaDecoder.decode_line(&line)),
+            vec![
+                ("GLGSV.in_view".to_owned(), 2.0),
+                ("GLGSV.snr.65".to_owned(), 33.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn multibyte_address_is_parse_error() {
+        for line in [
+            "$GPGGé,1,2".to_owned(),
+            "$éé,1".to_owned(),
+            sentence("GPÄ,1"),
+        ] {
+            let msgs = NmeaDecoder.decode_line(&line);
+            assert!(
+                matches!(msgs.first(), Some(MessageSerialThread::ParseError(_))),
+                "{}: {:?}",
+                line,
+                msgs
+            );
+        }
+    }
+}
@@ -23,6 +23,9 @@ use gtk::DrawingArea;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+
+use crate::util::XAxis;
+
 /// A single line
 #[derive(Debug)]
 pub(crate) struct Line {
@@ -51,34 +54,27 @@ pub(crate) struct Graph {
     pub(crate) auto_adjust_y: bool,   // enable to automatically adjust y axis
     pub(crate) lines: HashMap<String, Line>,
     pub(crate) pankti_sankya: f64, // use used while adding to point in lines to see last count of graphable input
+    pub(crate) x_axis: XAxis,      // what is value on pankti, to write it on scale
+    pub(crate) markers: Vec<(f64, String)>, // events like disconnection, drawn as line across stambh
 }
 
 impl Graph {
-    pub(crate) fn new(
-        area: DrawingArea,
-        scale_x_start: f64,
-        scale_x_size: f64,
-        scale_y_start: f64,
-        scale_y_size: f64,
-        draw_patch: bool,
-        draw_box: bool,
-        draw_baarik_box: bool,
-        auto_adjust_y: bool,
-        lines: HashMap<String, Line>,
-        pankti_sankya: f64,
-    ) -> Rc<RefCell<Self>> {
+    /// Graph with no lines on `area`, showing first 100 of pankti and stambh
+    pub(crate) fn new(area: DrawingArea) -> Rc<RefCell<Self>> {
         let graph = Rc::new(RefCell::new(Graph {
             area,
-            scale_x_start,
-            scale_x_size,
-            scale_y_start,
-            scale_y_size,
-            draw_patch,
-            draw_box,
-            draw_baarik_box,
-            auto_adjust_y,
-            lines,
-            pankti_sankya,
+            scale_x_start: 0.0,
+            scale_x_size: 100.0,
+            scale_y_start: 0.0,
+            scale_y_size: 100.0,
+            draw_patch: false,
+            draw_box: true,
+            draw_baarik_box: false,
+            auto_adjust_y: true,
+            lines: HashMap::new(),
+            pankti_sankya: 0.0,
+            x_axis: XAxis::Sankhya,
+            markers: Vec::new(),
         }));
 
         let graph_tmp = Rc::clone(&graph);
@@ -118,20 +114,17 @@ impl Graph {
 
     /// transform point to show on graph
     fn transform_on_graph(
-        p_start: f64,
-        s_start: f64,
+        &self,
         p: f64,
         s: f64,
         aa_dumm_pankti: f64,
         aa_dumm_stambh: f64,
-        scale_x_size: f64,
-        scale_y_size: f64,
         height: f64,
         stambh_scale_width: f64,
     ) -> (f64, f64) {
         (
-            ((p - p_start) * aa_dumm_pankti) / scale_x_size + stambh_scale_width,
-            height - ((s - s_start) * aa_dumm_stambh) / scale_y_size,
+            ((p - self.scale_x_start) * aa_dumm_pankti) / self.scale_x_size + stambh_scale_width,
+            height - ((s - self.scale_y_start) * aa_dumm_stambh) / self.scale_y_size,
         )
     }
 
@@ -179,28 +172,20 @@ impl Graph {
                     line.points[i]
                 };
 
-                let bindu_t = Graph::transform_on_graph(
-                    graph.scale_x_start,
-                    graph.scale_y_start,
+                let bindu_t = graph.transform_on_graph(
                     *p,
                     *s,
                     aa_dumm_pankti,
                     aa_dumm_stambh,
-                    graph.scale_x_size,
-                    graph.scale_y_size,
                     height,
                     stambh_scale_width,
                 );
 
-                let bindu_dumm_t = Graph::transform_on_graph(
-                    graph.scale_x_start,
-                    graph.scale_y_start,
+                let bindu_dumm_t = graph.transform_on_graph(
                     p_dumm,
                     s_dumm,
                     aa_dumm_pankti,
                     aa_dumm_stambh,
-                    graph.scale_x_size,
-                    graph.scale_y_size,
                     height,
                     stambh_scale_width,
                 );
@@ -225,6 +210,30 @@ impl Graph {
             }
         }
 
+        // draw markers with their text at top
+        ctx.set_source_rgb(0.8, 0.1, 0.1);
+        ctx.set_line_width(1.0);
+        ctx.set_dash(&[6.0, 4.0], 0.0);
+        for (x, text) in graph.markers.iter() {
+            let (mx, _) = graph.transform_on_graph(
+                *x,
+                graph.scale_y_start,
+                aa_dumm_pankti,
+                aa_dumm_stambh,
+                height,
+                stambh_scale_width,
+            );
+            if mx < stambh_scale_width {
+                continue;
+            }
+            ctx.move_to(mx, 0.0);
+            ctx.line_to(mx, height);
+            ctx.stroke().unwrap();
+            ctx.move_to(mx + 3.0, 12.0);
+            ctx.show_text(text).unwrap();
+        }
+        ctx.set_dash(&[], 0.0);
+
         // draw darker recragle over scales
         ctx.set_source_rgb(0.1, 0.4, 0.4);
         ctx.rectangle(0.0, 0.0, stambh_scale_width, height + pankti_scale_height);
@@ -235,7 +244,7 @@ impl Graph {
         // write numbers on pankti scale
         for i in 0..(rekha_sankhya_pankti as i32 + 1) {
             let text =
-                math::round::floor(i as f64 * anupat_pankti + graph.scale_x_start, 4).to_string();
+                Graph::pankti_label(graph.x_axis, i as f64 * anupat_pankti + graph.scale_x_start);
             let f = ctx.text_extents(&text).expect("Text dimension");
             ctx.move_to(
                 i as f64 * manjusa_maap - f.width + stambh_scale_width + f.height / 0.866,
@@ -262,6 +271,20 @@ impl Graph {
         }
     }
 
+    /// text to write on pankti scale, time of day is as hh:mm:ss.mmm
+    fn pankti_label(x_axis: XAxis, value: f64) -> String {
+        match x_axis {
+            XAxis::Sankhya => math::round::floor(value, 4).to_string(),
+            XAxis::HostClock => match glib::DateTime::from_unix_local(value.floor() as i64)
+                .and_then(|time| time.format("%H:%M:%S"))
+            {
+                Ok(time) => format!("{}.{:03}", time, (value.fract() * 1000.0) as u32),
+                Err(_) => math::round::floor(value, 3).to_string(),
+            },
+            _ => format!("{}s", math::round::floor(value, 3)),
+        }
+    }
+
     /// Adjust stambh and pankti as needed , trim lines and redraws
     pub(crate) fn redraw(&mut self) {
         let (mx_x, mi_x, mx_y, mi_y) = self.get_extremes();
@@ -340,6 +363,8 @@ impl Graph {
     // tims line form left side of line. Why left?? to avoid wasting time in ponits in range
     // it skips i point out of screen to keep a non terminating line experience
     pub(crate) fn trim_lines(&mut self) {
+        let scale_x_start = self.scale_x_start;
+        self.markers.retain(|(x, _)| *x >= scale_x_start);
         for (_, line) in self.lines.iter_mut() {
             let mut i = 0;
             while i < line.points.len() {
@@ -17,8 +17,11 @@
 
 //! Feel free to see through codes. Application is not written to be used as a library for other app. :)
 
+pub(crate) mod decoder;
 pub(crate) mod graph;
 pub(crate) mod port_util;
+pub(crate) mod rules;
+pub(crate) mod source;
 pub(crate) mod util;
 
 use glib::clone;
@@ -26,20 +29,27 @@ use gtk::prelude::*;
 
 use rand::Rng;
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::BufReader;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use decoder::binary;
 use graph::Graph;
 use port_util as putil;
 use util::Properties;
 
-// Building and propsuring GUI
-pub fn build_ui(app: &gtk::Application) {
+// Building and propsuring GUI, reading starts at once if port is given
+pub fn build_ui(app: &gtk::Application, start_port: Option<String>) {
     let props = Arc::new(Properties::default());
+    if let Some(start_port) = start_port {
+        if let Ok(mut a) = props.port.lock() {
+            *a = start_port;
+        }
+        if let Ok(mut a) = props.status.lock() {
+            *a = util::Status::PARIVARTIT;
+        }
+    }
     let ui_file = include_str!("ui.glade");
     let builder = gtk::Builder::from_string(ui_file);
 
@@ -72,20 +82,79 @@ pub fn build_ui(app: &gtk::Application) {
     save_window.add_button("_Save", gtk::ResponseType::Apply);
     save_window.add_button("_Cancel", gtk::ResponseType::Cancel);
 
+    // Settings Window
+    let settings_window = builder
+        .object::<gtk::Dialog>("settings_window")
+        .expect("Resource file missing!");
+    settings_window.set_transient_for(Some(&win));
+
+    // Diagnostics of lines which could not be read
+    let diagnostics_area = builder
+        .object::<gtk::TextView>("diagnostics_area")
+        .expect("Resource file missing!");
+    let diagnostics_label = builder
+        .object::<gtk::Label>("diagnostics_label")
+        .expect("Resource file missing!");
+    let parse_errors = Rc::new(Cell::new(0u64));
+
+    // Rules Window
+    let rules_window = builder
+        .object::<gtk::Dialog>("rules_window")
+        .expect("Resource file missing!");
+    rules_window.set_transient_for(Some(&win));
+    let rules_store = builder
+        .object::<gtk::ListStore>("rules_store")
+        .expect("Resource file missing!");
+    let rules_view = builder
+        .object::<gtk::TreeView>("rules_view")
+        .expect("Resource file missing!");
+    let rules_sample = builder
+        .object::<gtk::Entry>("rules_sample")
+        .expect("Resource file missing!");
+    let rules_result = builder
+        .object::<gtk::Label>("rules_result")
+        .expect("Resource file missing!");
+    // Connections Window, connections other than the one of toolbar
+    let connections_window = builder
+        .object::<gtk::Dialog>("connections_window")
+        .expect("Resource file missing!");
+    connections_window.set_transient_for(Some(&win));
+    let connections_store = builder
+        .object::<gtk::ListStore>("connections_store")
+        .expect("Resource file missing!");
+    let connections_view = builder
+        .object::<gtk::TreeView>("connections_view")
+        .expect("Resource file missing!");
+    let connection_name = builder
+        .object::<gtk::Entry>("connection_name")
+        .expect("Resource file missing!");
+    let connection_log = builder
+        .object::<gtk::TextView>("connection_log")
+        .expect("Resource file missing!");
+    let connection_send_entry = builder
+        .object::<gtk::Entry>("connection_send_entry")
+        .expect("Resource file missing!");
+    let connections: Rc<RefCell<Vec<Connection>>> = Rc::new(RefCell::new(Vec::new()));
+
+    let saved_rules = rules::load();
+    for (regex, scale, offset) in saved_rules.iter() {
+        rules_store.insert_with_values(None, &[(0, regex), (1, scale), (2, offset)]);
+    }
+    match rules::compile(&saved_rules) {
+        Ok(compiled) => {
+            if let Ok(mut a) = props.rules.lock() {
+                *a = compiled;
+            }
+        }
+        Err(e) => {
+            bar.push(1, &format!("Saved rules not used, {}", e));
+        }
+    }
+
     let graph = Graph::new(
         builder
             .object::<gtk::DrawingArea>("draw_area")
             .expect("Resource file missing!"),
-        0.0,
-        100.0,
-        0.0,
-        100.0,
-        false,
-        true,
-        false,
-        true,
-        HashMap::new(),
-        0.0,
     );
 
     win.show_all();
@@ -103,6 +172,22 @@ pub fn build_ui(app: &gtk::Application) {
     let port = builder
         .object::<gtk::ComboBoxText>("port")
         .expect("Resource file missing!");
+    let bondrate = builder
+        .object::<gtk::ComboBoxText>("bondrate")
+        .expect("Resource file missing!");
+    let modem_lines = builder
+        .object::<gtk::Label>("modem_lines")
+        .expect("Resource file missing!");
+    // messages of "Thread to manage Serial Port", see below
+    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    // commands for port, which is owned by "Thread to manage Serial Port"
+    let (commands, commands_receiver) = std::sync::mpsc::channel::<util::PortCommand>();
+    let terminator = builder
+        .object::<gtk::ComboBoxText>("terminator")
+        .expect("Resource file missing!");
+    let terminator_byte = builder
+        .object::<gtk::Entry>("terminator_byte")
+        .expect("Resource file missing!");
     let send_entry = builder
         .object::<gtk::Entry>("send_entry")
         .expect("Resource file missing!");
@@ -123,6 +208,21 @@ pub fn build_ui(app: &gtk::Application) {
                 save_window.present();
                 None
             })),
+            "settings_menu_activate" => Box::new(clone!(@weak settings_window => @default-return None, move |_| {
+                settings_window.show();
+                settings_window.present();
+                None
+            })),
+            "rules_menu_activate" => Box::new(clone!(@weak rules_window => @default-return None, move |_| {
+                rules_window.show();
+                rules_window.present();
+                None
+            })),
+            "connections_menu_activate" => Box::new(clone!(@weak connections_window => @default-return None, move |_| {
+                connections_window.show();
+                connections_window.present();
+                None
+            })),
             "gtk_main_quit" => Box::new(clone!(@weak save_window => @default-return None, move |_| {
                 save_window.show();
                 save_window.present();
@@ -192,48 +292,275 @@ pub fn build_ui(app: &gtk::Application) {
                 let mut tmp_graph = graph.borrow_mut();
                 tmp_graph.pankti_sankya = 0.0;
                 tmp_graph.lines.clear();
+                tmp_graph.markers.clear();
                 tmp_graph.redraw();
                 None
             })),
-            "bondrate_changed" => Box::new(clone!(@weak props => @default-return None, move |a| {
+            "bondrate_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
                 let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
-                props.bondrate.store(btn.active_text().unwrap().parse::<u32>().unwrap_or(9600u32), Ordering::SeqCst);
+                let text = btn.active_text().map(|t| t.to_string()).unwrap_or_default();
+                let entry = btn.child().and_then(|c| c.downcast::<gtk::Entry>().ok());
+                match putil::parse_baud(&text) {
+                    Ok(rate) => {
+                        props.bondrate.store(rate, Ordering::SeqCst);
+                        if let Some(entry) = entry { entry.style_context().remove_class("error"); }
+                    }
+                    // last valid rate is kept
+                    Err(e) => {
+                        bar.push(1, &e);
+                        if let Some(entry) = entry { entry.style_context().add_class("error"); }
+                    }
+                }
                 None
             })),
-            "port_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+            "auto_baud_clicked" => Box::new(clone!(@weak props, @weak bar, @strong sender => @default-return None, move |_| {
+                // port is closed by reader, so that it can be opened at other rates
+                match props.status.lock() {
+                    Ok(mut a) => { *a = util::Status::AVRODTIH },
+                    Err(_) => { bar.push(1, "Can't Avrodhit"); return None; }
+                }
+                bar.push(1, "Detecting baud rate...");
+                let sender = sender.clone();
+                tokio::task::spawn_blocking(move || {
+                    let msg = if !putil::wait_for_release(&props, std::time::Duration::from_secs(3)) {
+                        util::MessageSerialThread::Status("Port is still open by reader".to_owned())
+                    } else {
+                        match putil::detect_baud(&props) {
+                            Ok(rate) => util::MessageSerialThread::BaudDetected(rate),
+                            Err(e) => util::MessageSerialThread::Status(e),
+                        }
+                    };
+                    sender.send(msg).ok();
+                });
+                None
+            })),
+            "data_bits_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(id) = btn.active_id() {
+                    match props.line.lock() {
+                        Ok(mut a) => { a.set_data_bits(&id); },
+                        Err(_) => { bar.push(1, "Can't set Data bits"); }
+                    }
+                }
+                None
+            })),
+            "parity_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(id) = btn.active_id() {
+                    match props.line.lock() {
+                        Ok(mut a) => { a.set_parity(&id); },
+                        Err(_) => { bar.push(1, "Can't set Parity"); }
+                    }
+                }
+                None
+            })),
+            "stop_bits_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(id) = btn.active_id() {
+                    match props.line.lock() {
+                        Ok(mut a) => { a.set_stop_bits(&id); },
+                        Err(_) => { bar.push(1, "Can't set Stop bits"); }
+                    }
+                }
+                None
+            })),
+            "flow_control_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(id) = btn.active_id() {
+                    match props.line.lock() {
+                        Ok(mut a) => { a.set_flow_control(&id); },
+                        Err(_) => { bar.push(1, "Can't set Flow control"); }
+                    }
+                }
+                None
+            })),
+            "decoder_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(kind) = btn.active_id().and_then(|id| util::DecoderKind::from_id(&id)) {
+                    match props.decoder.lock() {
+                        Ok(mut a) => { *a = kind },
+                        Err(_) => { bar.push(1, "Can't set Decoder"); }
+                    }
+                }
+                None
+            })),
+            "csv_delimiter_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                let text = entry.text();
+                let delimiter = match text.as_str() {
+                    "tab" | "\\t" => '\t',
+                    _ => match text.chars().next() {
+                        Some(c) => c,
+                        None => { return None; }
+                    },
+                };
+                match props.csv_delimiter.lock() {
+                    Ok(mut a) => { *a = delimiter },
+                    Err(_) => { bar.push(1, "Can't set CSV delimiter"); }
+                }
+                None
+            })),
+            "encoding_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(encoding) = btn.active_id().and_then(|id| util::Encoding::from_id(&id)) {
+                    match props.encoding.lock() {
+                        Ok(mut a) => { *a = encoding },
+                        Err(_) => { bar.push(1, "Can't set Encoding"); }
+                    }
+                }
+                None
+            })),
+            "terminator_changed" | "terminator_byte_changed" => Box::new(clone!(@weak props, @weak bar, @weak terminator, @weak terminator_byte => @default-return None, move |_| {
+                let byte = match util::Terminator::parse_byte(&terminator_byte.text()) {
+                    Some(byte) => byte,
+                    None => {
+                        bar.push(1, "Invalid line end byte, use a character or hex like 0x3E");
+                        return None;
+                    }
+                };
+                if let Some(val) = terminator.active_id().and_then(|id| util::Terminator::from_id(&id, byte)) {
+                    match props.terminator.lock() {
+                        Ok(mut a) => { *a = val },
+                        Err(_) => { bar.push(1, "Can't set Line ending"); }
+                    }
+                }
+                None
+            })),
+            "idle_flush_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                match entry.text().trim().parse::<u32>() {
+                    Ok(val) => props.idle_flush.store(val, Ordering::SeqCst),
+                    Err(_) => { bar.push(1, "Invalid time of silence, use milliseconds like 200"); }
+                }
+                None
+            })),
+            "binary_framing_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(framing) = btn.active_id().and_then(|id| binary::Framing::from_id(&id)) {
+                    match props.binary_format.lock() {
+                        Ok(mut a) => { a.framing = framing },
+                        Err(_) => { bar.push(1, "Can't set Framing"); }
+                    }
+                }
+                None
+            })),
+            "binary_crc_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(crc) = btn.active_id().and_then(|id| binary::Crc::from_id(&id)) {
+                    match props.binary_format.lock() {
+                        Ok(mut a) => { a.crc = crc },
+                        Err(_) => { bar.push(1, "Can't set CRC"); }
+                    }
+                }
+                None
+            })),
+            "binary_endian_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                let big_endian = btn.active_id().map(|id| id == "big").unwrap_or(false);
+                match props.binary_format.lock() {
+                    Ok(mut a) => { a.big_endian = big_endian },
+                    Err(_) => { bar.push(1, "Can't set Byte order"); }
+                }
+                None
+            })),
+            "udp_aliases_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                match util::parse_aliases(&entry.text()) {
+                    Ok(aliases) => match props.aliases.lock() {
+                        Ok(mut a) => { *a = aliases },
+                        Err(_) => { bar.push(1, "Can't set Names of senders"); }
+                    },
+                    Err(e) => { bar.push(1, &format!("Invalid names of senders: {}", e)); }
+                }
+                None
+            })),
+            "mqtt_topics_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                let topics = entry.text()
+                    .split(',')
+                    .map(|a| a.trim().to_owned())
+                    .filter(|a| !a.is_empty())
+                    .collect::<Vec<String>>();
+                if topics.is_empty() {
+                    bar.push(1, "At least one MQTT topic is needed, like #");
+                    return None;
+                }
+                match props.mqtt_topics.lock() {
+                    Ok(mut a) => { *a = topics },
+                    Err(_) => { bar.push(1, "Can't set MQTT topics"); }
+                }
+                None
+            })),
+            "binary_layout_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                match binary::BinaryFormat::parse_layout(&entry.text()) {
+                    Ok(fields) => match props.binary_format.lock() {
+                        Ok(mut a) => { a.fields = fields },
+                        Err(_) => { bar.push(1, "Can't set Layout"); }
+                    },
+                    Err(e) => { bar.push(1, &format!("Invalid layout: {}", e)); }
+                }
+                None
+            })),
+            "x_axis_changed" => Box::new(clone!(@weak props, @weak graph, @weak bar, @strong connections => @default-return None, move |a| {
                 let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
-                if let Some(val) = btn.active_text() {
-                    match props.port.lock() {
-                        Ok(mut a) => { *a = val.to_string() },
-                        Err(_) => { bar.push(1, "Can't set Port"); }
+                if let Some(x_axis) = btn.active_id().and_then(|id| util::XAxis::from_id(&id)) {
+                    match props.x_axis.lock() {
+                        Ok(mut a) => { *a = x_axis },
+                        Err(_) => { bar.push(1, "Can't set Pankti"); return None; }
+                    }
+                    // all connections draw on same graph
+                    for conn in connections.borrow().iter() {
+                        if let Ok(mut a) = conn.props.x_axis.lock() { *a = x_axis; }
                     }
+                    // points on old pankti can't be mixed with new
+                    let mut tmp_graph = graph.borrow_mut();
+                    tmp_graph.x_axis = x_axis;
+                    tmp_graph.pankti_sankya = 0.0;
+                    tmp_graph.lines.clear();
+                    tmp_graph.markers.clear();
+                    tmp_graph.redraw();
+                }
+                None
+            })),
+            "port_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                let val = match btn.active_id() {
+                    Some(id) => id.to_string(),
+                    // typed address like tcp://host:port, or text of a port which is gone,
+                    // where name is before two spaces
+                    None => match btn.child().and_then(|c| c.downcast::<gtk::Entry>().ok()) {
+                        Some(entry) => entry.text().split("  ").next().unwrap_or("").trim().to_owned(),
+                        None => return None,
+                    },
+                };
+                if val.is_empty() { return None; }
+                match props.port.lock() {
+                    Ok(mut a) => { *a = val },
+                    Err(_) => { bar.push(1, "Can't set Port"); }
                 }
                 None
             })),
             "refresh_port_clicked" => Box::new(clone!(@weak port, @weak bar, @weak props => @default-return None, move |_| {
-                port.remove_all();
                 match props.status.lock() {
                     Ok(mut a) => { *a = util::Status::AVRODTIH },
                     Err(_) => { bar.push(1, "Can't Avrodhit"); return None; }
                 }
                 bar.push(1, "Avrodhit");
-                match serialport::available_ports() {
-                    Ok(ports) => {
-                        if ports.len() == 0 { bar.push(1, "No port found!"); }
-                        for p in ports {
-                            port.append_text(p.port_name.as_str());
-                        }
-                    }, Err(_) => {
-                        bar.push(1, "No port found!");
-                    }
-                }
+                let ports = putil::port_list();
+                if ports.is_empty() { bar.push(1, "No port found!"); }
+                let selected = props.port.lock().map(|a| a.to_owned()).unwrap_or_default();
+                fill_ports(&port, &ports, &selected);
                 None
             })),
-            "jagrit_btn_clicked" => Box::new(clone!(@weak props, @weak graph, @weak bar => @default-return None, move |_| {
+            "jagrit_btn_clicked" => Box::new(clone!(@weak props, @weak graph, @weak bar, @weak diagnostics_area, @weak diagnostics_label, @strong parse_errors => @default-return None, move |_| {
+                clear_parse_errors(&parse_errors, &diagnostics_area, &diagnostics_label, &bar);
                 let mut tmp_graph = graph.borrow_mut();
                 tmp_graph.pankti_sankya = 0.0;
                 tmp_graph.lines.clear();
+                tmp_graph.markers.clear();
                 tmp_graph.redraw();
+                if let Ok(mut a) = props.started.lock() { *a = std::time::SystemTime::now(); }
                 bar.push(1, "Jagrit");
                 match props.status.lock() {
                     Ok(mut a) => { *a = util::Status::PARIVARTIT },
@@ -249,21 +576,61 @@ pub fn build_ui(app: &gtk::Application) {
                 }
                 None
             })),
-            "clear_log_clicked" => Box::new(clone!(@weak log_area => @default-return None, move |_| {
+            "dtr_toggled" => Box::new(clone!(@weak props, @strong commands => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ToggleToolButton>().unwrap();
+                props.dtr.store(btn.is_active(), Ordering::SeqCst);
+                commands.send(util::PortCommand::SetDtr(btn.is_active())).ok();
+                None
+            })),
+            "rts_toggled" => Box::new(clone!(@weak props, @strong commands => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ToggleToolButton>().unwrap();
+                props.rts.store(btn.is_active(), Ordering::SeqCst);
+                commands.send(util::PortCommand::SetRts(btn.is_active())).ok();
+                None
+            })),
+            "break_btn_clicked" => Box::new(clone!(@weak props, @strong commands => @default-return None, move |_| {
+                commands.send(util::PortCommand::Break(props.break_ms.load(Ordering::SeqCst))).ok();
+                None
+            })),
+            "reset_on_connect_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::ComboBoxText>().unwrap();
+                if let Some(reset) = btn.active_id().and_then(|id| util::ResetOnConnect::from_id(&id)) {
+                    match props.reset_on_connect.lock() {
+                        Ok(mut a) => { *a = reset },
+                        Err(_) => { bar.push(1, "Can't set Reset on connect"); }
+                    }
+                }
+                None
+            })),
+            "break_ms_changed" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+                let entry = a[0].get::<gtk::Entry>().unwrap();
+                match entry.text().trim().parse::<u32>() {
+                    Ok(val) => props.break_ms.store(val, Ordering::SeqCst),
+                    Err(_) => { bar.push(1, "Invalid Break duration, use milliseconds like 250"); }
+                }
+                None
+            })),
+            "auto_reconnect_toggled" => Box::new(clone!(@weak props => @default-return None, move |a| {
+                let btn = a[0].get::<gtk::CheckButton>().unwrap();
+                props.auto_reconnect.store(btn.is_active(), Ordering::SeqCst);
+                None
+            })),
+            "clear_log_clicked" => Box::new(clone!(@weak log_area, @weak bar, @weak diagnostics_area, @weak diagnostics_label, @strong parse_errors => @default-return None, move |_| {
                 log_area.buffer().expect("Couldn't get window").set_text("");
+                clear_parse_errors(&parse_errors, &diagnostics_area, &diagnostics_label, &bar);
                 None
             })),
-            "send_entry_key_press_event" => Box::new(clone!(@weak props, @weak bar => @default-return None, move |a| {
+            "send_entry_key_press_event" => Box::new(clone!(@weak props, @weak bar, @strong commands => @default-return None, move |a| {
                 let ev = a[1].get::<gdk::Event>().unwrap();
                 let ev: Result<gdk::EventKey,_> = gdk::FromEvent::from(ev);
                 if ev.unwrap().keyval() == gdk::keys::constants::Return {
                     let ent = a[0].get::<gtk::Entry>().unwrap();
-                    putil::send_text(&props, &ent, &bar);
+                    putil::send_text(&props, &commands, &ent, &bar);
                 }
                 Some(false.to_value())
             })),
-            "send_btn_clicked" => Box::new(clone!(@weak props, @weak bar, @weak send_entry => @default-return None, move |_| {
-                putil::send_text(&props, &send_entry, &bar);
+            "send_btn_clicked" => Box::new(clone!(@weak props, @weak bar, @weak send_entry, @strong commands => @default-return None, move |_| {
+                putil::send_text(&props, &commands, &send_entry, &bar);
                 None
             })),
             "about_window_delete" => Box::new(|a| {
@@ -276,6 +643,230 @@ pub fn build_ui(app: &gtk::Application) {
                 win.hide();
                 Some(true.to_value())
             }),
+            "settings_window_delete" => Box::new(|a| {
+                let win = a[0].get::<gtk::Dialog>().unwrap();
+                win.hide();
+                Some(true.to_value())
+            }),
+            "settings_window_close" => Box::new(clone!(@weak settings_window => @default-return None, move |_| {
+                settings_window.hide();
+                None
+            })),
+            "rules_window_delete" => Box::new(|a| {
+                let win = a[0].get::<gtk::Dialog>().unwrap();
+                win.hide();
+                Some(true.to_value())
+            }),
+            "rules_window_close" => Box::new(clone!(@weak rules_window => @default-return None, move |_| {
+                rules_window.hide();
+                None
+            })),
+            "rules_add_clicked" => Box::new(clone!(@weak rules_store => @default-return None, move |_| {
+                rules_store.insert_with_values(None, &[(0, &""), (1, &"1"), (2, &"0")]);
+                None
+            })),
+            "rules_remove_clicked" => Box::new(clone!(@weak rules_store, @weak rules_view => @default-return None, move |_| {
+                if let Some((_, iter)) = rules_view.selection().selected() {
+                    rules_store.remove(&iter);
+                }
+                None
+            })),
+            "rules_regex_edited" => Box::new(clone!(@weak rules_store => @default-return None, move |a| {
+                rules_cell_edited(&rules_store, 0, a);
+                None
+            })),
+            "rules_scale_edited" => Box::new(clone!(@weak rules_store => @default-return None, move |a| {
+                rules_cell_edited(&rules_store, 1, a);
+                None
+            })),
+            "rules_offset_edited" => Box::new(clone!(@weak rules_store => @default-return None, move |a| {
+                rules_cell_edited(&rules_store, 2, a);
+                None
+            })),
+            "rules_test_clicked" => Box::new(clone!(@weak rules_store, @weak rules_sample, @weak rules_result => @default-return None, move |_| {
+                let compiled = match rules::compile(&rules_in_store(&rules_store)) {
+                    Ok(a) => a,
+                    Err(e) => { rules_result.set_text(&e); return None; }
+                };
+                let points = rules::apply_all(&compiled, &rules_sample.text());
+                if points.is_empty() {
+                    rules_result.set_text("No match");
+                } else {
+                    let text = points.iter()
+                        .map(|(name, val)| format!("{} = {}", name, val))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    rules_result.set_text(&text);
+                }
+                None
+            })),
+            "rules_save_clicked" => Box::new(clone!(@weak props, @weak rules_store, @weak rules_result, @weak bar, @strong connections => @default-return None, move |_| {
+                let texts = rules_in_store(&rules_store);
+                let compiled = match rules::compile(&texts) {
+                    Ok(a) => a,
+                    Err(e) => { rules_result.set_text(&e); return None; }
+                };
+                for conn in connections.borrow().iter() {
+                    if let Ok(mut a) = conn.props.rules.lock() { *a = compiled.clone(); }
+                }
+                match props.rules.lock() {
+                    Ok(mut a) => { *a = compiled },
+                    Err(_) => { bar.push(1, "Can't set Rules"); return None; }
+                }
+                match rules::save(&texts) {
+                    Ok(_) => { rules_result.set_text("Saved"); },
+                    Err(e) => { rules_result.set_text(&format!("Rules are used but not saved: {}", e)); }
+                }
+                None
+            })),
+            "connections_window_delete" => Box::new(|a| {
+                let win = a[0].get::<gtk::Dialog>().unwrap();
+                win.hide();
+                Some(true.to_value())
+            }),
+            "connections_window_close" => Box::new(clone!(@weak connections_window => @default-return None, move |_| {
+                connections_window.hide();
+                None
+            })),
+            "connection_add_clicked" => Box::new(clone!(@weak props, @weak connections_store, @weak connections_view, @weak connection_name, @weak bar, @strong connections, @strong sender => @default-return None, move |_| {
+                let name = connection_name.text().trim().to_owned();
+                if name.is_empty() || name.contains('/') {
+                    bar.push(1, "Name of connection can't be empty or have /");
+                    return None;
+                }
+                if connections.borrow().iter().any(|a| a.props.name == name) {
+                    bar.push(1, &format!("Connection {} already exists", name));
+                    return None;
+                }
+                let conn = Arc::new(props.duplicate(&name));
+                let port = match conn.port.lock() {
+                    Ok(a) => a.to_owned(),
+                    Err(_) => { bar.push(1, "Can't add Connection"); return None; }
+                };
+                if port.is_empty() {
+                    bar.push(1, "Select a port in toolbar to add connection");
+                    return None;
+                }
+                if let Ok(mut a) = conn.status.lock() { *a = util::Status::PARIVARTIT; }
+                let bondrate = conn.bondrate.load(Ordering::SeqCst).to_string();
+                let iter = connections_store.insert_with_values(None, &[(0, &name), (1, &port), (2, &bondrate), (3, &status_label(util::Status::PARIVARTIT)), (4, &"")]);
+
+                // messages of connection are kept in its row and log, then shown like those of toolbar
+                let (conn_sender, conn_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+                let (commands, commands_receiver) = std::sync::mpsc::channel::<util::PortCommand>();
+                let log = gtk::TextBuffer::new(None::<&gtk::TextTagTable>);
+                conn_receiver.attach(None, clone!(@weak connections_store, @weak log, @strong name, @strong sender => @default-return glib::Continue(false), move |msg| {
+                    match &msg {
+                        util::MessageSerialThread::Msg(text, _) => {
+                            let text = text.strip_prefix(&format!("{}> ", name)).unwrap_or(text);
+                            log.insert(&mut log.end_iter(), &format!("{}\n", text));
+                        }
+                        util::MessageSerialThread::Status(text) => {
+                            let text = text.strip_prefix(&format!("{}: ", name)).unwrap_or(text);
+                            if let Some(iter) = connection_row(&connections_store, &name) {
+                                connections_store.set_value(&iter, 4, &text.to_value());
+                            }
+                        }
+                        _ => {}
+                    }
+                    glib::Continue(sender.send(msg).is_ok())
+                }));
+                spawn_reader(Arc::clone(&conn), commands_receiver, conn_sender);
+                connections.borrow_mut().push(Connection { props: conn, commands, log });
+                connections_view.selection().select_iter(&iter);
+                connection_name.set_text("");
+                None
+            })),
+            "connections_selection_changed" => Box::new(clone!(@weak connections_view, @weak connection_log, @strong connections => @default-return None, move |_| {
+                match selected_connection(&connections_view).and_then(|(index, _)| connections.borrow().get(index).map(|a| a.log.clone())) {
+                    Some(log) => connection_log.set_buffer(Some(&log)),
+                    None => connection_log.set_buffer(None::<&gtk::TextBuffer>),
+                }
+                None
+            })),
+            "connection_port_edited" => Box::new(clone!(@weak connections_store, @weak bar, @strong connections => @default-return None, move |a| {
+                let path = a[1].get::<String>().unwrap_or_default();
+                let port = a[2].get::<String>().unwrap_or_default().trim().to_owned();
+                let iter = connections_store.iter(&gtk::TreePath::from_string(&path))?;
+                let index = *connections_store.path(&iter)?.indices().first()? as usize;
+                if port.is_empty() {
+                    bar.push(1, "Port of connection can't be empty");
+                    return None;
+                }
+                if let Some(conn) = connections.borrow().get(index) {
+                    match conn.props.port.lock() {
+                        Ok(mut a) => { *a = port.clone() },
+                        Err(_) => { bar.push(1, "Can't set Port"); return None; }
+                    }
+                    connections_store.set_value(&iter, 1, &port.to_value());
+                    restart_if_running(&conn.props);
+                }
+                None
+            })),
+            "connection_bondrate_edited" => Box::new(clone!(@weak connections_store, @weak bar, @strong connections => @default-return None, move |a| {
+                let path = a[1].get::<String>().unwrap_or_default();
+                let text = a[2].get::<String>().unwrap_or_default();
+                let iter = connections_store.iter(&gtk::TreePath::from_string(&path))?;
+                let index = *connections_store.path(&iter)?.indices().first()? as usize;
+                let rate = match putil::parse_baud(&text) {
+                    Ok(rate) => rate,
+                    Err(e) => { bar.push(1, &e); return None; }
+                };
+                if let Some(conn) = connections.borrow().get(index) {
+                    conn.props.bondrate.store(rate, Ordering::SeqCst);
+                    connections_store.set_value(&iter, 2, &rate.to_string().to_value());
+                    restart_if_running(&conn.props);
+                }
+                None
+            })),
+            "connection_settings_clicked" => Box::new(clone!(@weak props, @weak connections_view, @weak bar, @strong connections => @default-return None, move |_| {
+                if let Some((index, _)) = selected_connection(&connections_view) {
+                    if let Some(conn) = connections.borrow().get(index) {
+                        conn.props.copy_settings(&props);
+                        restart_if_running(&conn.props);
+                        bar.push(1, &format!("{}: settings are used", conn.props.name));
+                    }
+                }
+                None
+            })),
+            "connection_send_clicked" => Box::new(clone!(@weak connections_view, @weak connection_send_entry, @weak bar, @strong connections => @default-return None, move |_| {
+                match selected_connection(&connections_view) {
+                    Some((index, _)) => {
+                        if let Some(conn) = connections.borrow().get(index) {
+                            putil::send_text(&conn.props, &conn.commands, &connection_send_entry, &bar);
+                        }
+                    }
+                    None => { bar.push(1, "Select a connection to send text"); }
+                }
+                None
+            })),
+            "connection_start_clicked" => Box::new(clone!(@weak connections_view, @strong connections => @default-return None, move |_| {
+                if let Some((index, _)) = selected_connection(&connections_view) {
+                    if let Some(conn) = connections.borrow().get(index) {
+                        if let Ok(mut a) = conn.props.status.lock() { *a = util::Status::PARIVARTIT; }
+                    }
+                }
+                None
+            })),
+            "connection_stop_clicked" => Box::new(clone!(@weak connections_view, @strong connections => @default-return None, move |_| {
+                if let Some((index, _)) = selected_connection(&connections_view) {
+                    if let Some(conn) = connections.borrow().get(index) {
+                        if let Ok(mut a) = conn.props.status.lock() { *a = util::Status::AVRODTIH; }
+                    }
+                }
+                None
+            })),
+            "connection_remove_clicked" => Box::new(clone!(@weak connections_store, @weak connections_view, @strong connections => @default-return None, move |_| {
+                if let Some((index, iter)) = selected_connection(&connections_view) {
+                    let mut tmp_connections = connections.borrow_mut();
+                    if index < tmp_connections.len() {
+                        tmp_connections.remove(index).props.closed.store(true, Ordering::SeqCst);
+                    }
+                    drop(tmp_connections);
+                    connections_store.remove(&iter);
+                }
+                None
+            })),
             "about_window_close" => Box::new(clone!(@weak about_window => @default-return None, move |_| {
                 about_window.hide();
                 None
@@ -314,6 +905,47 @@ pub fn build_ui(app: &gtk::Application) {
         _ => (),
     });
 
+    // State of connections is shown as it changes, like after a failed open
+    glib::timeout_add_local(
+        std::time::Duration::from_millis(500),
+        clone!(@weak connections_store, @strong connections => @default-return glib::Continue(false), move || {
+            for conn in connections.borrow().iter() {
+                let status = match conn.props.status.lock() {
+                    Ok(a) => a.to_owned(),
+                    Err(_) => continue,
+                };
+                if let Some(iter) = connection_row(&connections_store, &conn.props.name) {
+                    let shown = connections_store.value(&iter, 3).get::<String>().unwrap_or_default();
+                    if shown != status_label(status) {
+                        connections_store.set_value(&iter, 3, &status_label(status).to_value());
+                    }
+                }
+            }
+            glib::Continue(true)
+        }),
+    );
+
+    // Hotplug, list of ports is refreshed when devices come or go, connection is not touched
+    let selected = props.port.lock().map(|a| a.to_owned()).unwrap_or_default();
+    let mut known_ports = (putil::port_list(), selected);
+    fill_ports(&port, &known_ports.0, &known_ports.1);
+    glib::timeout_add_local(
+        std::time::Duration::from_secs(1),
+        clone!(@weak port, @weak props => @default-return glib::Continue(false), move || {
+            // port in config changes on reconnect
+            let selected = match props.port.lock() {
+                Ok(a) => a.to_owned(),
+                Err(_) => return glib::Continue(true),
+            };
+            let ports = putil::port_list();
+            if ports != known_ports.0 || selected != known_ports.1 {
+                fill_ports(&port, &ports, &selected);
+                known_ports = (ports, selected);
+            }
+            glib::Continue(true)
+        }),
+    );
+
     /*
         Thread to manage Serial Port
 
@@ -321,16 +953,8 @@ pub fn build_ui(app: &gtk::Application) {
         send it through mpsc (rx, tx) to a recever. Where it is added to Graph
         or Log is added to text area or any status is displayed in bar
     */
-    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
 
-    let tmp_props = Arc::clone(&props);
-    tokio::task::spawn(async move {
-        let mut bufread: Option<BufReader<Box<dyn serialport::SerialPort>>> = None;
-        let mut buf = String::new();
-        loop {
-            putil::serial_thread_work(&tmp_props, &mut bufread, &sender, &mut buf).await;
-        }
-    });
+    spawn_reader(Arc::clone(&props), commands_receiver, sender);
 
     // Reciver for MessageSerialThread from the "Thread to manage Serial Port" and works accordingly
     let full_log = builder
@@ -345,17 +969,111 @@ pub fn build_ui(app: &gtk::Application) {
             util::MessageSerialThread::Msg(text, msg_type) => {
                 receiver_for_msg(text, &msg_type, &full_log, &log_area);
             }
-            util::MessageSerialThread::Points(points) => {
-                receiver_for_points(points, &tmp_graph, &graph_data);
+            util::MessageSerialThread::Points(x, points) => {
+                receiver_for_points(x, points, &tmp_graph, &graph_data);
             }
             util::MessageSerialThread::Status(text) => {
                 bar.push(1, &text);
             }
+            util::MessageSerialThread::ModemLines(lines) => {
+                let level = |on: bool| if on { "●" } else { "○" };
+                modem_lines.set_text(&format!(
+                    "CTS {} DSR {} RI {} CD {}",
+                    level(lines.cts),
+                    level(lines.dsr),
+                    level(lines.ri),
+                    level(lines.cd)
+                ));
+            }
+            util::MessageSerialThread::Marker(x, text) => {
+                receiver_for_msg(
+                    format!("---- {} ----", text),
+                    &util::MessageSerialThreadMsgType::Log,
+                    &full_log,
+                    &log_area,
+                );
+                receiver_for_marker(x, text, &tmp_graph);
+            }
+            util::MessageSerialThread::BaudDetected(rate) => {
+                if let Some(entry) = bondrate
+                    .child()
+                    .and_then(|c| c.downcast::<gtk::Entry>().ok())
+                {
+                    entry.set_text(&rate.to_string());
+                }
+                bar.push(1, &format!("Baud rate detected: {}", rate));
+            }
+            util::MessageSerialThread::ParseError(error) => {
+                receiver_for_parse_error(
+                    error,
+                    &parse_errors,
+                    &diagnostics_area,
+                    &diagnostics_label,
+                    &bar,
+                );
+            }
         }
         glib::Continue(true)
     });
 }
 
+// Starts "Thread to manage Serial Port" for a connection
+fn spawn_reader(
+    props: Arc<Properties>,
+    commands: std::sync::mpsc::Receiver<util::PortCommand>,
+    sender: glib::Sender<util::MessageSerialThread>,
+) {
+    tokio::task::spawn(async move {
+        let mut state = putil::SerialState::new(commands);
+        while putil::serial_thread_work(&props, &mut state, &sender).await {}
+    });
+}
+
+// Connection of Connections window, it reads on its own thread and has its own log
+struct Connection {
+    props: Arc<Properties>,
+    commands: std::sync::mpsc::Sender<util::PortCommand>, // to send text to its port
+    log: gtk::TextBuffer,
+}
+
+// Word shown for status of a connection
+fn status_label(status: util::Status) -> &'static str {
+    match status {
+        util::Status::AVRODTIH | util::Status::SAYAN => "Avrodhit",
+        util::Status::JAGRIT => "Jagrit",
+        util::Status::PARIVARTIT => "Parivartit",
+    }
+}
+
+// Opens port of connection again with changed settings, if it is reading
+fn restart_if_running(props: &Properties) {
+    if let Ok(mut a) = props.status.lock() {
+        if let util::Status::JAGRIT = *a {
+            *a = util::Status::PARIVARTIT;
+        }
+    }
+}
+
+// Row of connection with name
+fn connection_row(store: &gtk::ListStore, name: &str) -> Option<gtk::TreeIter> {
+    let iter = store.iter_first()?;
+    loop {
+        if store.value(&iter, 0).get::<String>().ok().as_deref() == Some(name) {
+            return Some(iter);
+        }
+        if !store.iter_next(&iter) {
+            return None;
+        }
+    }
+}
+
+// Index in list of connections and row of selected connection
+fn selected_connection(view: &gtk::TreeView) -> Option<(usize, gtk::TreeIter)> {
+    let (model, iter) = view.selection().selected()?;
+    let index = *model.path(&iter)?.indices().first()?;
+    Some((index as usize, iter))
+}
+
 // Receives MessageSerialThread from Serial Port managing thread adds message to text area
 fn receiver_for_msg(
     text: String,
@@ -379,6 +1097,7 @@ fn receiver_for_msg(
 
 // Receives MessageSerialThread from Serial Port managing thread and add points to draw on graph
 fn receiver_for_points(
+    x: Option<f64>,
     points: Vec<(String, f64)>,
     graph: &Rc<RefCell<Graph>>,
     graph_data: &gtk::TextView,
@@ -386,7 +1105,12 @@ fn receiver_for_points(
     for (line, point) in points {
         let mut gp = graph.borrow_mut();
 
-        let sankhya = gp.pankti_sankya;
+        // without value of pankti, only counter can place the point
+        let sankhya = match (x, gp.x_axis) {
+            (Some(x), _) => x,
+            (None, util::XAxis::Sankhya) => gp.pankti_sankya,
+            (None, _) => continue,
+        };
         match gp.lines.get_mut(&line) {
             Some(val) => {
                 val.points.push((sankhya, point));
@@ -422,3 +1146,98 @@ fn receiver_for_points(
     }
     graph.borrow_mut().pankti_sankya += 1.0;
 }
+
+// Fills combo box of ports, with name of port as id. Port in config stays selected
+fn fill_ports(port: &gtk::ComboBoxText, ports: &[(String, String)], selected: &str) {
+    port.remove_all();
+    for (name, label) in ports {
+        port.append(Some(name), label);
+    }
+    if !port.set_active_id(Some(selected)) {
+        // address typed by user, like tcp://host:port
+        if let Some(entry) = port.child().and_then(|c| c.downcast::<gtk::Entry>().ok()) {
+            entry.set_text(selected);
+        }
+    }
+}
+
+// Receives marker from Serial Port managing thread and puts it on graph
+fn receiver_for_marker(x: Option<f64>, text: String, graph: &Rc<RefCell<Graph>>) {
+    let mut gp = graph.borrow_mut();
+    // without value of pankti, marker is put after last point
+    let x = match (x, gp.x_axis) {
+        (Some(x), _) => x,
+        (None, util::XAxis::Sankhya) => gp.pankti_sankya,
+        (None, _) => gp.get_extremes().0,
+    };
+    gp.markers.push((x, text));
+    gp.redraw();
+}
+
+// Receives ParseError from Serial Port managing thread, adds it to diagnostics and counts it
+fn receiver_for_parse_error(
+    error: util::ParseError,
+    count: &Cell<u64>,
+    diagnostics_area: &gtk::TextView,
+    diagnostics_label: &gtk::Label,
+    bar: &gtk::Statusbar,
+) {
+    count.set(count.get() + 1);
+    diagnostics_label.set_text(&format!("Parse errors ({})", count.get()));
+    bar.pop(2);
+    bar.push(2, &format!("Parse errors: {}", count.get()));
+
+    let buf = diagnostics_area
+        .buffer()
+        .expect("Couldn't get diagnostics_area");
+    buf.insert(
+        &mut buf.end_iter(),
+        &format!(
+            "column {}: {}\n{}\n{}^\n",
+            error.column,
+            error.reason,
+            error.line,
+            " ".repeat(error.column - 1)
+        ),
+    );
+    diagnostics_area.scroll_to_iter(&mut buf.end_iter(), 0.4, true, 0.0, 0.0);
+}
+
+// Forget parse errors of last session
+fn clear_parse_errors(
+    count: &Cell<u64>,
+    diagnostics_area: &gtk::TextView,
+    diagnostics_label: &gtk::Label,
+    bar: &gtk::Statusbar,
+) {
+    count.set(0);
+    diagnostics_label.set_text("Parse errors");
+    bar.pop(2);
+    if let Some(buf) = diagnostics_area.buffer() {
+        buf.set_text("");
+    }
+}
+
+// Text of rules (regex, scale, offset) in list of Rules window
+fn rules_in_store(store: &gtk::ListStore) -> Vec<(String, String, String)> {
+    let mut texts = Vec::new();
+    if let Some(iter) = store.iter_first() {
+        loop {
+            let get = |col: i32| store.value(&iter, col).get::<String>().unwrap_or_default();
+            texts.push((get(0), get(1), get(2)));
+            if !store.iter_next(&iter) {
+                break;
+            }
+        }
+    }
+    texts
+}
+
+// Set text of edited cell, args of signal are renderer, path and new text
+fn rules_cell_edited(store: &gtk::ListStore, col: u32, args: &[glib::Value]) {
+    let path = args[1].get::<String>().unwrap_or_default();
+    let text = args[2].get::<String>().unwrap_or_default();
+    if let Some(iter) = store.iter(&gtk::TreePath::from_string(&path)) {
+        store.set_value(&iter, col, &text.to_value());
+    }
+}
@@ -16,12 +16,75 @@
 
 use gio::{prelude::*, ApplicationFlags};
 
+const USAGE: &str = "Usage: tarangam [--stdin | --file PATH]
+
+  --stdin       plot lines piped to tarangam, like `candump can0 | tarangam --stdin`
+  --file PATH   plot lines written to a named pipe or to a growing file, like `tail -f`";
+
 #[tokio::main]
 async fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let port = match port_from_args(args.get(1..).unwrap_or(&[])) {
+        Ok(Options::Port(port)) => port,
+        Ok(Options::Help) => {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let app = gtk::Application::new(Some("sng.tarangm"), ApplicationFlags::default());
 
     app.connect_activate(move |app| {
-        tarangam::build_ui(app);
+        tarangam::build_ui(app, port.clone());
     });
-    app.run();
+    // options are of Tarangam, GTK would refuse them
+    app.run_with_args(args.get(..1).unwrap_or(&[]));
+}
+
+/// What options ask for
+#[derive(Debug, PartialEq)]
+enum Options {
+    Help,
+    Port(Option<String>), // port to start reading at once
+}
+
+/// Port to start reading at once, given by options
+fn port_from_args(args: &[String]) -> Result<Options, String> {
+    match args {
+        [] => Ok(Options::Port(None)),
+        [flag] if flag == "--help" || flag == "-h" => Ok(Options::Help),
+        [flag] if flag == "--stdin" => Ok(Options::Port(Some("stdin://".to_owned()))),
+        [flag, path] if flag == "--file" => Ok(Options::Port(Some(format!("file://{}", path)))),
+        [flag] if flag == "--file" => Err("--file needs a path".to_owned()),
+        _ => Err(format!("Unknown options: {}", args.join(" "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        port_from_args(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn options() {
+        let port = |a: &str| Ok(Options::Port(Some(a.to_owned())));
+        assert_eq!(parse(&[]), Ok(Options::Port(None)));
+        assert_eq!(parse(&["--help"]), Ok(Options::Help));
+        assert_eq!(parse(&["-h"]), Ok(Options::Help));
+        assert_eq!(parse(&["--stdin"]), port("stdin://"));
+        assert_eq!(parse(&["--file", "/tmp/log"]), port("file:///tmp/log"));
+        assert_eq!(parse(&["--file"]), Err("--file needs a path".to_owned()));
+        assert_eq!(
+            parse(&["--port", "x"]),
+            Err("Unknown options: --port x".to_owned())
+        );
+        assert!(parse(&["--stdin", "--file", "a"]).is_err());
+    }
 }
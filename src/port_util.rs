@@ -19,107 +19,667 @@
 
 use gtk::prelude::*;
 
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, prelude::*};
 use std::sync::atomic::Ordering;
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::decoder::{self, Decoder};
+use crate::rules;
+use crate::source::{self, Source};
 use crate::{util, util::Properties};
 
-// Controls the thread and read from serial port
+/// Name of key giving timestamp of device
+const TIME_KEY: &str = "t";
+
+/// Bytes without line ending after which they are shown as a line anyway
+const MAX_LINE: usize = 4096;
+
+/// Bytes taken by one read, big enough for any UDP datagram
+const READ_CHUNK: usize = 65536;
+
+/// Reads done each time thread works, so that many senders don't fall behind
+const MAX_READS: usize = 64;
+
+/// How often CTS, DSR, RI and CD are read
+const MODEM_POLL: Duration = Duration::from_millis(200);
+
+/// Rates tried by auto detect, most used first
+const BAUD_CANDIDATES: [u32; 14] = [
+    115200, 9600, 57600, 38400, 19200, 74880, 230400, 250000, 460800, 921600, 1000000, 2000000,
+    4800, 2400,
+];
+
+/// How often ports are searched for device which was disconnected
+const RECONNECT_POLL: Duration = Duration::from_millis(500);
+
+/// Longest wait between tries to connect again to a network address
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Time for which bytes are read at each rate
+const BAUD_LISTEN: Duration = Duration::from_millis(700);
+
+/// State of "Thread to manage Serial Port", kept between calls of serial_thread_work
+pub(crate) struct SerialState {
+    source: Option<Box<dyn Source>>,
+    streams: HashMap<String, Stream>, // by sender, only "" for sources having one sender
+    bytes: Vec<u8>,                   // read buffer, kept for every read
+    commands: mpsc::Receiver<util::PortCommand>,
+    modem: Option<util::ModemLines>,
+    modem_read: Instant,
+    usb: Option<UsbIdentity>, // device opened on Start, to find it again after unplug
+    retry: Instant,
+    backoff: Duration, // wait before next try to connect again, grows for network addresses
+    connected: bool,   // port was open once since Start, so opening it again is reconnecting
+}
+
+/// Bytes of one sender, with own decoder so that lines of senders are not mixed
+struct Stream {
+    buf: Vec<u8>,
+    reads: VecDeque<(usize, SystemTime)>, // end of bytes of each read in buf, and when it came
+    last_read: Instant,
+    decoder: Box<dyn Decoder>,
+    clock: DeviceClock,
+}
+
+impl Stream {
+    fn new(config: &Properties, source: Option<&dyn Source>) -> Self {
+        Stream {
+            buf: Vec::new(),
+            reads: VecDeque::new(),
+            last_read: Instant::now(),
+            decoder: stream_decoder(config, source),
+            clock: DeviceClock::new(),
+        }
+    }
+
+    /// Keeps bytes of a read till their line ends, with time they came
+    fn push(&mut self, bytes: &[u8], arrived: SystemTime) {
+        self.buf.extend_from_slice(bytes);
+        self.reads.push_back((self.buf.len(), arrived));
+        self.last_read = Instant::now();
+    }
+
+    /// Forgets bytes of line of len, gives time of read which brought its last byte
+    fn take_arrival(&mut self, len: usize) -> SystemTime {
+        let arrived = match self.reads.iter().find(|(end, _)| *end >= len) {
+            Some((_, arrived)) => *arrived,
+            None => SystemTime::now(),
+        };
+        self.reads.retain(|(end, _)| *end > len);
+        for (end, _) in self.reads.iter_mut() {
+            *end -= len;
+        }
+        arrived
+    }
+
+    /// Decodes lines which have ended with time each line came, `whole` takes rest of
+    /// bytes as a line too
+    fn decode_lines(
+        &mut self,
+        config: &Properties,
+        whole: bool,
+    ) -> Vec<(SystemTime, Vec<util::MessageSerialThread>)> {
+        let encoding = match config.encoding.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => util::Encoding::Utf8,
+        };
+        let terminator = match config.terminator.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => util::Terminator::Lf,
+        };
+        // prompts like `>>> ` have no line ending, they are shown after silence
+        let idle_flush = config.idle_flush.load(Ordering::SeqCst);
+        let idle = whole
+            || (idle_flush > 0
+                && self.last_read.elapsed() >= Duration::from_millis(idle_flush as u64));
+        let mut msgs = Vec::new();
+        loop {
+            let (end, next) = match terminator.find(&self.buf) {
+                Some(a) => a,
+                None if self.buf.len() >= MAX_LINE || (idle && !self.buf.is_empty()) => {
+                    (self.buf.len(), self.buf.len())
+                }
+                None => break,
+            };
+            let line = self.buf.drain(..next).take(end).collect::<Vec<u8>>();
+            let arrived = self.take_arrival(next);
+            // other half of \r\n is left when terminator is only \r or \n
+            let line = match line.iter().position(|b| *b != b'\r' && *b != b'\n') {
+                Some(start) => &line[start..],
+                None => continue,
+            };
+            let line = match line.iter().rposition(|b| *b != b'\r' && *b != b'\n') {
+                Some(last) => &line[..=last],
+                None => continue,
+            };
+            let line = encoding.decode(line);
+            let decoded = self.decoder.decode_line(&line);
+            msgs.push((arrived, apply_rules(config, &line, decoded)));
+        }
+        msgs
+    }
+}
+
+/// Line of device which decoder left as log becomes points if it matches extraction rules.
+/// Lines having points and messages made by decoder itself are left as they are
+fn apply_rules(
+    config: &Properties,
+    line: &str,
+    msgs: Vec<util::MessageSerialThread>,
+) -> Vec<util::MessageSerialThread> {
+    if msgs
+        .iter()
+        .any(|msg| matches!(msg, util::MessageSerialThread::Points(..)))
+    {
+        return msgs;
+    }
+    let points = match config.rules.lock() {
+        Ok(rules) if !rules.is_empty() => rules::apply_all(&rules, line),
+        _ => return msgs,
+    };
+    if points.is_empty() {
+        return msgs;
+    }
+
+    let mut points = Some(points);
+    let mut expanded = Vec::with_capacity(msgs.len() + 1);
+    for msg in msgs {
+        match msg {
+            util::MessageSerialThread::Msg(text, util::MessageSerialThreadMsgType::Log)
+                if text.trim() == line.trim() && points.is_some() =>
+            {
+                expanded.push(util::MessageSerialThread::Points(
+                    None,
+                    points.take().unwrap_or_default(),
+                ));
+                expanded.push(util::MessageSerialThread::Msg(
+                    text,
+                    util::MessageSerialThreadMsgType::Point,
+                ));
+            }
+            msg => expanded.push(msg),
+        }
+    }
+    expanded
+}
+
+/// Decoder of source if it has one, or decoder selected in config
+fn stream_decoder(config: &Properties, source: Option<&dyn Source>) -> Box<dyn Decoder> {
+    match source.and_then(|s| s.decoder()) {
+        Some(decoder) => decoder,
+        None => decoder::new_decoder(config),
+    }
+}
+
+/// What tells a USB device apart, name of port may change when it is plugged again
+#[derive(Debug, Clone, PartialEq)]
+struct UsbIdentity {
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+}
+
+impl SerialState {
+    pub(crate) fn new(commands: mpsc::Receiver<util::PortCommand>) -> Self {
+        SerialState {
+            source: None,
+            streams: HashMap::new(),
+            bytes: vec![0u8; READ_CHUNK],
+            commands,
+            modem: None,
+            modem_read: Instant::now(),
+            usb: None,
+            retry: Instant::now(),
+            backoff: RECONNECT_POLL,
+            connected: false,
+        }
+    }
+
+    /// Makes opened port ready for reading, levels of lines asked before are applied
+    fn attach(&mut self, config: &Properties, mut source: Box<dyn Source>) {
+        // levels asked before connecting are in config, so old commands are not needed
+        while self.commands.try_recv().is_ok() {}
+        source.set_dtr(config.dtr.load(Ordering::SeqCst)).ok();
+        source.set_rts(config.rts.load(Ordering::SeqCst)).ok();
+        self.modem = None;
+        // clock is kept, so that graph goes on after reconnecting
+        for stream in self.streams.values_mut() {
+            stream.buf.clear();
+            stream.reads.clear();
+            stream.decoder = stream_decoder(config, Some(source.as_ref()));
+        }
+        self.backoff = RECONNECT_POLL;
+        self.connected = true;
+        self.source = Some(source);
+    }
+
+    /// Puts value of pankti in points as asked, and send messages of sender to graph and log.
+    /// Returns false if receiver is gone
+    fn send(
+        &mut self,
+        config: &Properties,
+        origin: &str,
+        msgs: Vec<util::MessageSerialThread>,
+        arrived: SystemTime,
+        sender: &glib::Sender<util::MessageSerialThread>,
+    ) -> bool {
+        let x_axis = match config.x_axis.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => util::XAxis::Sankhya,
+        };
+
+        // time of arrival, for pankti of host time
+        let host_x = match x_axis {
+            util::XAxis::HostClock => arrived.duration_since(UNIX_EPOCH).ok(),
+            util::XAxis::HostRelative => match config.started.lock() {
+                Ok(started) => arrived.duration_since(*started).ok(),
+                Err(_) => None,
+            },
+            _ => None,
+        }
+        .map(|a| a.as_secs_f64());
+
+        let source = self.source.as_deref();
+        let clock = &mut self
+            .streams
+            .entry(origin.to_owned())
+            .or_insert_with(|| Stream::new(config, source))
+            .clock;
+        let mut msgs = msgs.into_iter().peekable();
+        while let Some(msg) = msgs.next() {
+            let msg = match (msg, x_axis) {
+                (util::MessageSerialThread::Points(_, mut points), util::XAxis::DeviceMillis)
+                | (util::MessageSerialThread::Points(_, mut points), util::XAxis::DeviceMicros) => {
+                    match points.iter().position(|(name, _)| name == TIME_KEY) {
+                        Some(index) => {
+                            let ticks = if let util::XAxis::DeviceMillis = x_axis {
+                                1000.0
+                            } else {
+                                1_000_000.0
+                            };
+                            let x = clock.unwrap(points.remove(index).1) / ticks;
+                            util::MessageSerialThread::Points(Some(x), points)
+                        }
+                        // points can't be put on pankti of device time, line of them is told
+                        None => {
+                            let line = match msgs.peek() {
+                                Some(util::MessageSerialThread::Msg(
+                                    text,
+                                    util::MessageSerialThreadMsgType::Point,
+                                )) => text.to_owned(),
+                                _ => points
+                                    .iter()
+                                    .map(|(name, val)| format!("{}={}", name, val))
+                                    .collect::<Vec<String>>()
+                                    .join(" "),
+                            };
+                            util::MessageSerialThread::ParseError(util::ParseError {
+                                line,
+                                column: 1,
+                                reason: format!("missing {}", TIME_KEY),
+                            })
+                        }
+                    }
+                }
+                (
+                    util::MessageSerialThread::Points(_, points),
+                    util::XAxis::HostClock | util::XAxis::HostRelative,
+                ) => util::MessageSerialThread::Points(host_x, points),
+                (
+                    util::MessageSerialThread::Marker(_, text),
+                    util::XAxis::HostClock | util::XAxis::HostRelative,
+                ) => util::MessageSerialThread::Marker(host_x, text),
+                (msg, _) => msg,
+            };
+            let msg = match with_name(origin, msg).and_then(|a| with_name(&config.name, a)) {
+                Some(msg) => msg,
+                None => continue,
+            };
+            if sender.send(msg).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Opens source on a thread for blocking work, as connecting to a server may wait for seconds
+async fn open_source(config: &Arc<Properties>) -> io::Result<Box<dyn Source>> {
+    let config = Arc::clone(config);
+    match tokio::task::spawn_blocking(move || source::open(&config)).await {
+        Ok(result) => result,
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+/// Whether port in config is a network address
+fn is_network_port(config: &Properties) -> bool {
+    match config.port.lock() {
+        Ok(a) => source::is_network(&a),
+        Err(_) => false,
+    }
+}
+
+/// Sends message of thread itself with name of connection. Returns false if receiver is gone
+fn send_named(
+    config: &Properties,
+    msg: util::MessageSerialThread,
+    sender: &glib::Sender<util::MessageSerialThread>,
+) -> bool {
+    match with_name(&config.name, msg) {
+        Some(msg) => sender.send(msg).is_ok(),
+        None => true,
+    }
+}
+
+/// Puts name of connection or sender in message, so their lines are apart like `left/temp`.
+/// Lines of port are only shown for connection of toolbar
+fn with_name(name: &str, msg: util::MessageSerialThread) -> Option<util::MessageSerialThread> {
+    if name.is_empty() {
+        return Some(msg);
+    }
+    Some(match msg {
+        util::MessageSerialThread::Msg(text, msg_type) => {
+            util::MessageSerialThread::Msg(format!("{}> {}", name, text), msg_type)
+        }
+        util::MessageSerialThread::Points(x, points) => util::MessageSerialThread::Points(
+            x,
+            points
+                .into_iter()
+                .map(|(line, val)| {
+                    // value without a name, like payload of MQTT topic, is named after sender
+                    if line.is_empty() {
+                        (name.to_owned(), val)
+                    } else {
+                        (format!("{}/{}", name, line), val)
+                    }
+                })
+                .collect(),
+        ),
+        util::MessageSerialThread::Status(text) => {
+            util::MessageSerialThread::Status(format!("{}: {}", name, text))
+        }
+        util::MessageSerialThread::ParseError(mut error) => {
+            error.reason = format!("{}: {}", name, error.reason);
+            util::MessageSerialThread::ParseError(error)
+        }
+        util::MessageSerialThread::Marker(x, text) => {
+            util::MessageSerialThread::Marker(x, format!("{} {}", name, text))
+        }
+        util::MessageSerialThread::ModemLines(_) => return None,
+        msg => msg,
+    })
+}
+
+/// Makes timestamps of device continuous when 32 bit counter like millis() wraps around
+struct DeviceClock {
+    last: Option<f64>,
+    offset: f64,
+}
+
+impl DeviceClock {
+    fn new() -> Self {
+        DeviceClock {
+            last: None,
+            offset: 0.0,
+        }
+    }
+
+    fn unwrap(&mut self, raw: f64) -> f64 {
+        const WRAP: f64 = 4294967296.0; // 2^32
+        if let Some(last) = self.last {
+            if raw < last {
+                if last - raw > WRAP / 2.0 {
+                    // counter wrapped around
+                    self.offset += WRAP;
+                } else {
+                    // device restarted, continue from where it was
+                    self.offset += last - raw;
+                }
+            }
+        }
+        self.last = Some(raw);
+        raw + self.offset
+    }
+}
+
+// Controls the thread and read from serial port, returns false when window is closed
 pub(crate) async fn serial_thread_work(
     config: &Arc<Properties>,
-    bufread: &mut Option<BufReader<Box<dyn serialport::SerialPort>>>,
+    state: &mut SerialState,
     sender: &glib::Sender<util::MessageSerialThread>,
-    buf: &mut String,
-) {
+) -> bool {
+    if config.closed.load(Ordering::SeqCst) {
+        state.source = None;
+        return false;
+    }
     let status = match config.status.try_lock() {
         Ok(a) => a.to_owned(),
         Err(_) => {
-            return;
+            return true;
         }
     };
 
     match status {
         util::Status::AVRODTIH => {
-            *bufread = None;
+            state.source = None;
             match config.status.lock() {
                 Ok(mut a) => *a = util::Status::SAYAN,
                 Err(_) => {
-                    return;
+                    return true;
                 }
             };
         }
         util::Status::JAGRIT => {
-            if let Some(read) = bufread {
-                if let Ok(_) = read.read_line(buf) {
-                    for line in buf.lines() {
-                        if line.len() == 0 {
-                            continue;
-                        } else if line.starts_with("#") {
-                            let mut points: Vec<(String, f64)> = Vec::new();
-                            for (index, line) in line[1..].split(" ").enumerate() {
-                                let part = line.split("=");
-                                let part = part.into_iter().collect::<Vec<&str>>();
-                                if part.len() == 1 {
-                                    let num = match part[0].trim().parse::<f64>() {
-                                        Ok(val) => val,
-                                        Err(_) => {
-                                            continue;
-                                        }
-                                    };
-
-                                    points.push((index.to_string(), num));
-                                } else if part.len() == 2 {
-                                    points.push((
-                                        part[0].trim().to_owned(),
-                                        part[1].parse::<f64>().unwrap(),
-                                    ));
-                                }
+            let mut msgs = Vec::new();
+            let mut decoded = Vec::new(); // messages of every sender, with time they came
+            let mut disconnected = false;
+            if let Some(read) = &mut state.source {
+                while let Ok(command) = state.commands.try_recv() {
+                    if let Err(e) = run_command(read, command).await {
+                        msgs.push(util::MessageSerialThread::Status(e));
+                    }
+                }
+
+                if state.modem_read.elapsed() >= MODEM_POLL {
+                    state.modem_read = Instant::now();
+                    if let Some(lines) = read.modem_lines() {
+                        if state.modem != Some(lines) {
+                            state.modem = Some(lines);
+                            msgs.push(util::MessageSerialThread::ModemLines(lines));
+                        }
+                    }
+                }
+                for text in read.notices() {
+                    msgs.push(util::MessageSerialThread::Status(text));
+                }
+
+                // bytes are read as they are, so a bad byte can't take a line with it
+                let bytes = &mut state.bytes;
+                for _ in 0..MAX_READS {
+                    let len = match read.read(bytes) {
+                        Ok(len) if len > 0 => len,
+                        Ok(_) => break,
+                        Err(e) => {
+                            disconnected = is_disconnect(&e);
+                            break;
+                        }
+                    };
+                    let arrived = SystemTime::now();
+                    let origin = match read.origin() {
+                        Some(origin) => match config.aliases.lock() {
+                            Ok(aliases) => aliases.get(&origin).cloned().unwrap_or(origin),
+                            Err(_) => origin,
+                        },
+                        None => String::new(),
+                    };
+                    let stream = state
+                        .streams
+                        .entry(origin.clone())
+                        .or_insert_with(|| Stream::new(config, Some(read.as_ref())));
+                    if stream.decoder.is_binary() {
+                        stream.last_read = Instant::now();
+                        let msgs = stream.decoder.decode_bytes(&bytes[..len]);
+                        decoded.push((origin, arrived, msgs));
+                    } else {
+                        stream.push(&bytes[..len], arrived);
+                        // datagram is a whole message, even without line ending
+                        if read.is_datagram() {
+                            for (arrived, msgs) in stream.decode_lines(config, true) {
+                                decoded.push((origin.clone(), arrived, msgs));
                             }
-                            sender
-                                .send(util::MessageSerialThread::Points(points))
-                                .unwrap();
-                            sender
-                                .send(util::MessageSerialThread::Msg(
-                                    line.to_owned(),
-                                    util::MessageSerialThreadMsgType::Point,
-                                ))
-                                .unwrap();
-                        } else {
-                            sender
-                                .send(util::MessageSerialThread::Msg(
-                                    line.to_owned(),
-                                    util::MessageSerialThreadMsgType::Log,
-                                ))
-                                .unwrap();
                         }
                     }
-                    buf.clear();
+                }
+                for (origin, stream) in state.streams.iter_mut() {
+                    if !stream.decoder.is_binary() {
+                        for (arrived, msgs) in stream.decode_lines(config, false) {
+                            decoded.push((origin.to_owned(), arrived, msgs));
+                        }
+                    }
                 }
             }
 
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-        }
-        util::Status::PARIVARTIT => {
             let port = match config.port.lock() {
                 Ok(a) => a.to_owned(),
-                Err(_) => {
-                    return;
-                }
+                Err(_) => String::new(),
             };
-            let p = match serialport::new(&port, config.bondrate.load(Ordering::SeqCst)).open() {
+            // network addresses are always connected again, as connections drop now and then
+            let network = source::is_network(&port);
+            let auto_reconnect =
+                network || (config.auto_reconnect.load(Ordering::SeqCst) && state.usb.is_some());
+            if disconnected {
+                state.source = None;
+                state.retry = Instant::now();
+                msgs.push(util::MessageSerialThread::Marker(
+                    None,
+                    "disconnected".to_owned(),
+                ));
+                if network {
+                    msgs.push(util::MessageSerialThread::Status(format!(
+                        "Connection to {} lost, connecting again",
+                        port
+                    )));
+                } else if auto_reconnect {
+                    msgs.push(util::MessageSerialThread::Status(
+                        "Port disconnected, waiting for device to come back".to_owned(),
+                    ));
+                } else {
+                    if let Ok(mut a) = config.status.lock() {
+                        *a = util::Status::SAYAN;
+                    }
+                    msgs.push(util::MessageSerialThread::Status(
+                        "Port disconnected".to_owned(),
+                    ));
+                }
+            } else if state.source.is_none() && state.retry.elapsed() >= state.backoff {
+                state.retry = Instant::now();
+                if !auto_reconnect {
+                    if let Ok(mut a) = config.status.lock() {
+                        *a = util::Status::SAYAN;
+                    }
+                } else if network {
+                    match open_source(config).await {
+                        Ok(s) => {
+                            let (marker, status) = if state.connected {
+                                ("reconnected", "Reconnected to")
+                            } else {
+                                ("connected", "Connected to")
+                            };
+                            state.attach(config, s);
+                            msgs.push(util::MessageSerialThread::Marker(None, marker.to_owned()));
+                            msgs.push(util::MessageSerialThread::Status(format!(
+                                "{} {}",
+                                status, port
+                            )));
+                        }
+                        // server may be down for long, so it is asked less and less often
+                        Err(_) => state.backoff = (state.backoff * 2).min(MAX_BACKOFF),
+                    }
+                } else if let Some(port) = state.usb.as_ref().and_then(find_usb_port) {
+                    if let Ok(mut a) = config.port.lock() {
+                        *a = port.clone();
+                    }
+                    // device may not be ready just after it appears, so it is tried again
+                    if let Ok(p) = open_source(config).await {
+                        state.attach(config, p);
+                        msgs.push(util::MessageSerialThread::Marker(
+                            None,
+                            "reconnected".to_owned(),
+                        ));
+                        msgs.push(util::MessageSerialThread::Status(format!(
+                            "Reconnected to {}",
+                            port
+                        )));
+                    }
+                }
+            }
+            for (origin, arrived, msgs) in decoded {
+                if !msgs.is_empty() && !state.send(config, &origin, msgs, arrived, sender) {
+                    return false;
+                }
+            }
+            if !state.send(config, "", msgs, SystemTime::now(), sender) {
+                return false;
+            }
+
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        util::Status::PARIVARTIT => {
+            // port open before is closed first, as it may be opened again with other settings
+            state.source = None;
+            state.connected = false;
+            let mut p = match open_source(config).await {
                 Ok(p) => p,
-                Err(_) => {
-                    return;
+                // server may come up later, so it is tried again like a lost connection
+                Err(e) if is_network_port(config) => {
+                    state.streams.clear();
+                    state.retry = Instant::now();
+                    state.backoff = RECONNECT_POLL;
+                    if let Ok(mut a) = config.status.lock() {
+                        *a = util::Status::JAGRIT;
+                    }
+                    let msg = util::MessageSerialThread::Status(format!(
+                        "Failed to connect: {}, trying again",
+                        e
+                    ));
+                    return send_named(config, msg, sender);
+                }
+                Err(e) => {
+                    // settings like parity may not be supported by adapter, don't retry forever
+                    if let Ok(mut a) = config.status.lock() {
+                        *a = util::Status::SAYAN;
+                    }
+                    let msg =
+                        util::MessageSerialThread::Status(format!("Failed to open port: {}", e));
+                    return send_named(config, msg, sender);
                 }
             };
 
-            *bufread = Some(BufReader::new(p));
+            let reset = match config.reset_on_connect.lock() {
+                Ok(a) => a.to_owned(),
+                Err(_) => util::ResetOnConnect::None,
+            };
+            if let Err(e) = reset_board(&mut p, reset).await {
+                let msg =
+                    util::MessageSerialThread::Status(format!("Failed to reset board: {}", e));
+                if !send_named(config, msg, sender) {
+                    return false;
+                }
+            }
+            state.usb = match config.port.lock() {
+                Ok(a) => usb_identity(&a),
+                Err(_) => None,
+            };
+            state.streams.clear();
+            state.attach(config, p);
             match config.status.try_lock() {
                 Ok(mut a) => *a = util::Status::JAGRIT,
                 Err(_) => {
-                    return;
+                    return true;
                 }
             };
         }
@@ -127,10 +687,267 @@ pub(crate) async fn serial_thread_work(
             tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
         }
     }
+    true
+}
+
+/// Ports with text telling what they are, USB devices first.
+/// Text is like `/dev/ttyUSB0  QinHeng USB Serial (1a86:7523) SN 1234`
+pub(crate) fn port_list() -> Vec<(String, String)> {
+    let mut ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(_) => return Vec::new(),
+    };
+    ports.sort_by_key(|p| {
+        let usb = matches!(p.port_type, serialport::SerialPortType::UsbPort(_));
+        (!usb, p.port_name.clone())
+    });
+    ports
+        .into_iter()
+        .map(|p| {
+            let about = match &p.port_type {
+                serialport::SerialPortType::UsbPort(info) => {
+                    let mut about = Vec::new();
+                    about.extend(info.manufacturer.clone());
+                    about.extend(info.product.clone());
+                    about.push(format!("({:04x}:{:04x})", info.vid, info.pid));
+                    about.extend(info.serial_number.as_ref().map(|sn| format!("SN {}", sn)));
+                    about.join(" ")
+                }
+                serialport::SerialPortType::PciPort => "PCI".to_owned(),
+                serialport::SerialPortType::BluetoothPort => "Bluetooth".to_owned(),
+                serialport::SerialPortType::Unknown => String::new(),
+            };
+            let label = if about.is_empty() {
+                p.port_name.clone()
+            } else {
+                format!("{}  {}", p.port_name, about)
+            };
+            (p.port_name, label)
+        })
+        .collect()
 }
 
-// // Sends text through Serial Post to device
-pub(crate) fn send_text(config: &Arc<Properties>, entry: &gtk::Entry, bar: &gtk::Statusbar) {
+/// Whether error of read means that device is gone, and not just that nothing came
+fn is_disconnect(e: &std::io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::Interrupted
+    )
+}
+
+/// USB identity of port, None if it is not a USB device
+fn usb_identity(port_name: &str) -> Option<UsbIdentity> {
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|p| p.port_name == port_name)
+        .and_then(|p| match p.port_type {
+            serialport::SerialPortType::UsbPort(info) => Some(UsbIdentity {
+                vid: info.vid,
+                pid: info.pid,
+                serial_number: info.serial_number,
+            }),
+            _ => None,
+        })
+}
+
+/// Name of port of USB device, which may be other than before
+fn find_usb_port(usb: &UsbIdentity) -> Option<String> {
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|p| match &p.port_type {
+            serialport::SerialPortType::UsbPort(info) => {
+                info.vid == usb.vid
+                    && info.pid == usb.pid
+                    && info.serial_number == usb.serial_number
+            }
+            _ => false,
+        })
+        .map(|p| p.port_name)
+}
+
+/// Run command of window on port, error tells what failed
+async fn run_command(
+    source: &mut Box<dyn Source>,
+    command: util::PortCommand,
+) -> Result<(), String> {
+    match command {
+        util::PortCommand::SetDtr(level) => source
+            .set_dtr(level)
+            .map_err(|e| format!("Failed to set DTR: {}", e)),
+        util::PortCommand::SetRts(level) => source
+            .set_rts(level)
+            .map_err(|e| format!("Failed to set RTS: {}", e)),
+        util::PortCommand::Break(ms) => {
+            source
+                .set_break(true)
+                .map_err(|e| format!("Failed to send Break: {}", e))?;
+            tokio::time::sleep(Duration::from_millis(ms as u64)).await;
+            source
+                .set_break(false)
+                .map_err(|e| format!("Failed to clear Break: {}", e))
+        }
+        util::PortCommand::Write(bytes) => source
+            .write(&bytes)
+            .map_err(|e| format!("Failed to send text: {}", e)),
+    }
+}
+
+/// Pulse DTR and RTS to reset board, as done by avrdude and esptool
+async fn reset_board(
+    source: &mut Box<dyn Source>,
+    reset: util::ResetOnConnect,
+) -> std::io::Result<()> {
+    match reset {
+        util::ResetOnConnect::None => {}
+        util::ResetOnConnect::Arduino => {
+            source.set_dtr(false)?;
+            source.set_rts(false)?;
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            source.set_dtr(true)?;
+            source.set_rts(true)?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        util::ResetOnConnect::Esp32 => {
+            // DTR drives IO0 and RTS drives EN, both are inverted by the board
+            source.set_dtr(false)?;
+            source.set_rts(true)?;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            source.set_rts(false)?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    Ok(())
+}
+
+/// Opens port selected in config with its baud rate and line settings
+pub(crate) fn open_port(
+    config: &Properties,
+) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    open_port_at(config, config.bondrate.load(Ordering::SeqCst))
+}
+
+/// Opens port selected in config with given baud rate and line settings of config
+fn open_port_at(
+    config: &Properties,
+    bondrate: u32,
+) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    let port = match config.port.lock() {
+        Ok(a) => a.to_owned(),
+        Err(_) => {
+            return Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                "Can't get port",
+            ))
+        }
+    };
+    let line = match config.line.lock() {
+        Ok(a) => a.to_owned(),
+        Err(_) => util::LineSettings::default(),
+    };
+    serialport::new(&port, bondrate)
+        .data_bits(line.data_bits)
+        .parity(line.parity)
+        .stop_bits(line.stop_bits)
+        .flow_control(line.flow_control)
+        .open()
+}
+
+/// Parse baud rate typed by user, `_` and `,` between digits are allowed like 1_000_000
+pub(crate) fn parse_baud(text: &str) -> Result<u32, String> {
+    let digits = text
+        .trim()
+        .chars()
+        .filter(|c| *c != '_' && *c != ',')
+        .collect::<String>();
+    match digits.parse::<u32>() {
+        Ok(0) => Err("Baud rate can't be 0".to_owned()),
+        Ok(rate) => Ok(rate),
+        Err(_) => Err(format!("Invalid baud rate {}", text.trim())),
+    }
+}
+
+/// Share of bytes which look like text, from 0 to 1
+fn text_score(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let text = bytes
+        .iter()
+        .filter(|b| matches!(b, b' '..=b'~' | b'\r' | b'\n' | b'\t'))
+        .count();
+    text as f64 / bytes.len() as f64
+}
+
+/// Waits till reader has closed the port after it was asked to stop, that is till status
+/// is SAYAN. Returns false if it takes longer than timeout
+pub(crate) fn wait_for_release(config: &Properties, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(a) = config.status.lock() {
+            if let util::Status::SAYAN = *a {
+                return true;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// Listens on port at every candidate rate and gives rate at which bytes look most like text.
+/// Blocks for some seconds, port must not be open by reader
+pub(crate) fn detect_baud(config: &Properties) -> Result<u32, String> {
+    if let Ok(port) = config.port.lock() {
+        if !source::is_serial(&port) {
+            return Err("Baud rate can be detected on serial port only".to_owned());
+        }
+    }
+    let mut best: Option<(u32, f64)> = None;
+    for rate in BAUD_CANDIDATES.iter() {
+        let mut port = match open_port_at(config, *rate) {
+            Ok(p) => p,
+            Err(e) => return Err(format!("Failed to open port: {}", e)),
+        };
+        port.set_timeout(Duration::from_millis(50)).ok();
+        port.clear(serialport::ClearBuffer::Input).ok();
+
+        let mut bytes = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let start = Instant::now();
+        while start.elapsed() < BAUD_LISTEN {
+            if let Ok(len) = port.read(&mut chunk) {
+                bytes.extend_from_slice(&chunk[..len]);
+            }
+        }
+        // a few bytes can look like text by chance
+        if bytes.len() < 16 {
+            continue;
+        }
+        let score = text_score(&bytes);
+        if score > 0.98 {
+            return Ok(*rate);
+        }
+        if best.map(|(_, s)| score > s).unwrap_or(true) {
+            best = Some((*rate, score));
+        }
+    }
+    match best {
+        Some((rate, score)) if score > 0.8 => Ok(rate),
+        Some(_) => Err("Baud rate not found, text doesn't look right at any rate".to_owned()),
+        None => Err("Baud rate not found, device sent nothing".to_owned()),
+    }
+}
+
+// Sends text through Serial Port to device, by "Thread to manage Serial Port" which owns the port
+pub(crate) fn send_text(
+    config: &Arc<Properties>,
+    commands: &mpsc::Sender<util::PortCommand>,
+    entry: &gtk::Entry,
+    bar: &gtk::Statusbar,
+) {
     let status = match config.status.try_lock() {
         Ok(a) => a.to_owned(),
         Err(_) => {
@@ -138,26 +955,142 @@ pub(crate) fn send_text(config: &Arc<Properties>, entry: &gtk::Entry, bar: &gtk:
         }
     };
     if let util::Status::JAGRIT = status {
-        let port = match config.port.lock() {
-            Ok(a) => a.to_owned(),
+        let bytes = entry.text().as_bytes().to_vec();
+        match commands.send(util::PortCommand::Write(bytes)) {
+            Ok(_) => entry.set_text(""),
             Err(_) => {
-                bar.push(1, "Failed to set port!");
-                return;
+                bar.push(1, "Failed to send text!");
             }
-        };
+        }
+    } else {
+        bar.push(1, "Press Start to send text");
+    }
+}
 
-        let mut p = match serialport::new(port, config.bondrate.load(Ordering::SeqCst)).open() {
-            Ok(p) => p,
-            Err(_) => {
-                bar.push(1, "Failed to change port!");
-                return;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_apply_only_to_lines_of_device() {
+        let config = Properties::default();
+        if let Ok(mut rules) = config.rules.lock() {
+            *rules = vec![rules::Rule::new(r"temp (?P<temp>[0-9.]+)", "", "").unwrap()];
+        }
+        let log = |text: &str| {
+            util::MessageSerialThread::Msg(text.to_owned(), util::MessageSerialThreadMsgType::Log)
         };
 
-        unsafe {
-            p.write_all(entry.text().to_string().as_bytes_mut())
-                .unwrap();
+        let msgs = apply_rules(&config, "temp 21.5", vec![log("temp 21.5")]);
+        assert!(matches!(
+            msgs.as_slice(),
+            [
+                util::MessageSerialThread::Points(None, points),
+                util::MessageSerialThread::Msg(_, util::MessageSerialThreadMsgType::Point)
+            ] if points == &vec![("temp".to_owned(), 21.5)]
+        ));
+
+        // message made by decoder, not the line itself
+        let msgs = apply_rules(&config, "temp 3", vec![log("Invalid checksum: temp 3")]);
+        assert!(matches!(
+            msgs.as_slice(),
+            [util::MessageSerialThread::Msg(
+                _,
+                util::MessageSerialThreadMsgType::Log
+            )]
+        ));
+
+        // line which already has points
+        let msgs = apply_rules(
+            &config,
+            "#temp 4",
+            vec![util::MessageSerialThread::Points(None, Vec::new())],
+        );
+        assert!(matches!(
+            msgs.as_slice(),
+            [util::MessageSerialThread::Points(None, points)] if points.is_empty()
+        ));
+    }
+
+    #[test]
+    fn lines_keep_time_of_their_read() {
+        let config = Properties::default();
+        let mut stream = Stream::new(&config, None);
+        let time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        stream.push(b"#a=1\n#a=", time(1));
+        stream.push(b"2\n#a", time(2));
+        stream.push(b"=3\n", time(3));
+        let times = stream
+            .decode_lines(&config, false)
+            .into_iter()
+            .map(|(arrived, _)| arrived)
+            .collect::<Vec<SystemTime>>();
+        assert_eq!(times, vec![time(1), time(2), time(3)]);
+
+        // line flushed later keeps time of its last read
+        stream.push(b"#a=4", time(4));
+        assert!(stream.decode_lines(&config, false).is_empty());
+        stream.push(b"\n#a=5", time(5));
+        let times = stream
+            .decode_lines(&config, true)
+            .into_iter()
+            .map(|(arrived, _)| arrived)
+            .collect::<Vec<SystemTime>>();
+        assert_eq!(times, vec![time(5), time(5)]);
+        assert!(stream.reads.is_empty());
+    }
+
+    #[test]
+    fn value_without_name_is_named_after_sender() {
+        let msg = util::MessageSerialThread::Points(
+            None,
+            vec![("".to_owned(), 1.0), ("ax".to_owned(), 2.0)],
+        );
+        assert!(matches!(
+            with_name("home/imu", msg),
+            Some(util::MessageSerialThread::Points(None, points))
+                if points == vec![("home/imu".to_owned(), 1.0), ("home/imu/ax".to_owned(), 2.0)]
+        ));
+    }
+
+    #[test]
+    fn typed_baud_rates() {
+        assert_eq!(parse_baud("115200"), Ok(115200));
+        assert_eq!(parse_baud(" 1_000_000 "), Ok(1_000_000));
+        assert_eq!(parse_baud("2,000,000"), Ok(2_000_000));
+        assert!(parse_baud("0").is_err());
+        assert!(parse_baud("-9600").is_err());
+        assert!(parse_baud("9600 baud").is_err());
+        assert!(parse_baud("99999999999").is_err());
+    }
+
+    #[test]
+    fn stopped_reader_releases_port() {
+        let config = Properties::default();
+        if let Ok(mut a) = config.status.lock() {
+            *a = util::Status::AVRODTIH;
         }
-        entry.set_text("");
+        assert!(!wait_for_release(&config, Duration::from_millis(30)));
+        if let Ok(mut a) = config.status.lock() {
+            *a = util::Status::SAYAN;
+        }
+        assert!(wait_for_release(&config, Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn device_clock_wraps_around() {
+        let mut clock = DeviceClock::new();
+        assert_eq!(clock.unwrap(4294967000.0), 4294967000.0);
+        assert_eq!(clock.unwrap(4294967295.0), 4294967295.0);
+        assert_eq!(clock.unwrap(200.0), 4294967496.0);
+        assert_eq!(clock.unwrap(300.0), 4294967596.0);
+    }
+
+    #[test]
+    fn device_clock_goes_on_after_restart() {
+        let mut clock = DeviceClock::new();
+        clock.unwrap(5000.0);
+        assert_eq!(clock.unwrap(10.0), 5000.0);
+        assert_eq!(clock.unwrap(20.0), 5010.0);
     }
 }
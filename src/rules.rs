@@ -0,0 +1,197 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+
+//! Rules to take points out of log lines using regex with named capture groups
+
+use regex::Regex;
+
+use std::path::PathBuf;
+
+/// A rule, value of every named group becomes a point as `value * scale + offset`
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub(crate) regex: Regex,
+    pub(crate) scale: f64,
+    pub(crate) offset: f64,
+}
+
+impl Rule {
+    /// Make rule from text of regex, scale and offset. Empty scale is 1 and empty offset is 0
+    pub(crate) fn new(regex: &str, scale: &str, offset: &str) -> Result<Self, String> {
+        let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+        if regex.capture_names().flatten().count() == 0 {
+            return Err("No named group like (?P<temp>[0-9.]+)".to_owned());
+        }
+        let scale = match scale.trim() {
+            "" => 1.0,
+            s => s.parse::<f64>().map_err(|_| format!("Bad scale {}", s))?,
+        };
+        let offset = match offset.trim() {
+            "" => 0.0,
+            s => s.parse::<f64>().map_err(|_| format!("Bad offset {}", s))?,
+        };
+        Ok(Rule {
+            regex,
+            scale,
+            offset,
+        })
+    }
+
+    /// Points from named groups of first match in line
+    pub(crate) fn apply(&self, line: &str) -> Vec<(String, f64)> {
+        let mut points = Vec::new();
+        if let Some(caps) = self.regex.captures(line) {
+            for name in self.regex.capture_names().flatten() {
+                if let Some(val) = caps.name(name).and_then(|m| m.as_str().parse::<f64>().ok()) {
+                    points.push((name.to_owned(), val * self.scale + self.offset));
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Points from all rules which match line
+pub(crate) fn apply_all(rules: &[Rule], line: &str) -> Vec<(String, f64)> {
+    rules.iter().flat_map(|rule| rule.apply(line)).collect()
+}
+
+/// File where rules are saved, a rule in each line as `scale<TAB>offset<TAB>regex`
+fn config_file() -> PathBuf {
+    glib::user_config_dir().join("tarangam").join("rules.txt")
+}
+
+/// Read text of rules (regex, scale, offset) from config file
+pub(crate) fn load() -> Vec<(String, String, String)> {
+    match std::fs::read_to_string(config_file()) {
+        Ok(text) => from_text(&text),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Rules (regex, scale, offset) from saved text, regex is last so it may have tabs
+fn from_text(text: &str) -> Vec<(String, String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let scale = parts.next()?;
+            let offset = parts.next()?;
+            let regex = parts.next()?;
+            Some((regex.to_owned(), scale.to_owned(), offset.to_owned()))
+        })
+        .collect()
+}
+
+/// Write text of rules (regex, scale, offset) to config file
+pub(crate) fn save(rules: &[(String, String, String)]) -> std::io::Result<()> {
+    let path = config_file();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, to_text(rules))
+}
+
+/// Text of rules (regex, scale, offset) to save
+fn to_text(rules: &[(String, String, String)]) -> String {
+    rules
+        .iter()
+        .map(|(regex, scale, offset)| format!("{}\t{}\t{}\n", scale, offset, regex))
+        .collect()
+}
+
+/// Compile text of rules (regex, scale, offset), rules with empty regex are left
+pub(crate) fn compile(texts: &[(String, String, String)]) -> Result<Vec<Rule>, String> {
+    texts
+        .iter()
+        .enumerate()
+        .filter(|(_, (regex, _, _))| !regex.is_empty())
+        .map(|(index, (regex, scale, offset))| {
+            Rule::new(regex, scale, offset).map_err(|e| format!("Rule {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(regex: &str, scale: &str, offset: &str) -> (String, String, String) {
+        (regex.to_owned(), scale.to_owned(), offset.to_owned())
+    }
+
+    #[test]
+    fn bad_rules() {
+        assert!(Rule::new("temp=([0-9.]+)", "", "").is_err());
+        assert!(Rule::new("temp=(?P<temp>[0-9.]+", "", "").is_err());
+        assert_eq!(
+            Rule::new("(?P<t>[0-9]+)", "x2", "").unwrap_err(),
+            "Bad scale x2"
+        );
+        assert_eq!(
+            Rule::new("(?P<t>[0-9]+)", "", "one").unwrap_err(),
+            "Bad offset one"
+        );
+    }
+
+    #[test]
+    fn scale_and_offset() {
+        let rule = Rule::new("t=(?P<t>[0-9.-]+)", "0.5", "-10").unwrap();
+        assert_eq!(rule.apply("t=40"), vec![("t".to_owned(), 10.0)]);
+        assert!(rule.apply("h=40").is_empty());
+    }
+
+    #[test]
+    fn groups_without_numbers_are_left() {
+        let rule = Rule::new("t=(?P<t>\\S+)( h=(?P<h>[0-9]+))?( s=(?P<s>\\S+))?", "", "").unwrap();
+        assert_eq!(
+            rule.apply("t=1 h=2"),
+            vec![("t".to_owned(), 1.0), ("h".to_owned(), 2.0)]
+        );
+        // h didn't match and s isn't a number
+        assert_eq!(rule.apply("t=1 s=ok"), vec![("t".to_owned(), 1.0)]);
+        assert!(rule.apply("t=hot").is_empty());
+    }
+
+    #[test]
+    fn compile_leaves_empty_rows() {
+        let rules = compile(&[
+            text("", "", ""),
+            text("a=(?P<a>[0-9]+)", "", ""),
+            text("", "2", ""),
+        ])
+        .unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(apply_all(&rules, "a=3"), vec![("a".to_owned(), 3.0)]);
+
+        let error = compile(&[
+            text("a=(?P<a>[0-9]+)", "", ""),
+            text("", "", ""),
+            text("b=([0-9]+)", "", ""),
+        ])
+        .unwrap_err();
+        assert!(error.starts_with("Rule 3: "), "{}", error);
+    }
+
+    #[test]
+    fn saved_text_round_trip() {
+        let rules = vec![
+            text("a\t(?P<a>[0-9]+)", "2", "-1"),
+            text("b=(?P<b>[0-9]+)", "", ""),
+        ];
+        assert_eq!(from_text(&to_text(&rules)), rules);
+    }
+}
@@ -0,0 +1,163 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! Sources are what bytes are read from, like serial port or TCP socket
+
+mod file;
+mod mqtt;
+mod pipe;
+mod rfc2217;
+mod serial;
+mod tcp;
+mod udp;
+
+use std::io;
+use std::sync::atomic::Ordering;
+
+use crate::decoder::Decoder;
+use crate::port_util;
+use crate::util::{self, ModemLines, Properties};
+
+/// Connection to device, read by "Thread to manage Serial Port"
+///
+/// Reading must not wait. When nothing has come it gives `WouldBlock` or
+/// `TimedOut`, other errors mean that connection is lost.
+pub(crate) trait Source: Send {
+    /// Read bytes which have come
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write all bytes, may wait for a short while
+    fn write(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Err(unsupported("Sending"))
+    }
+
+    /// Set level of DTR line
+    fn set_dtr(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported("DTR"))
+    }
+
+    /// Set level of RTS line
+    fn set_rts(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported("RTS"))
+    }
+
+    /// Start or stop sending Break
+    fn set_break(&mut self, _on: bool) -> io::Result<()> {
+        Err(unsupported("Break"))
+    }
+
+    /// Levels of CTS, DSR, RI and CD, None if source has no such lines
+    fn modem_lines(&mut self) -> Option<ModemLines> {
+        None
+    }
+
+    /// Who sent bytes of last read, for sources getting bytes from many senders
+    fn origin(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether every read is a whole message, so bytes without line ending are a line too
+    fn is_datagram(&self) -> bool {
+        false
+    }
+
+    /// Messages about the connection which came while reading, like a setting which
+    /// server couldn't make, for status bar
+    fn notices(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Decoder for sources whose messages have a format of their own, like MQTT.
+    /// None if decoder selected by user is used
+    fn decoder(&self) -> Option<Box<dyn Decoder>> {
+        None
+    }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::other(format!("{} is not supported by this connection", what))
+}
+
+/// Whether port is a network address like `tcp://host:port`, which is connected again when lost
+pub(crate) fn is_network(port: &str) -> bool {
+    ["tcp://", "udp://", "rfc2217://", "mqtt://"]
+        .iter()
+        .any(|scheme| port.starts_with(scheme))
+}
+
+/// Whether port is a local serial port, and not an address like `tcp://host:port`
+pub(crate) fn is_serial(port: &str) -> bool {
+    !port.contains("://")
+}
+
+/// Whether file is a named pipe, which is read like stdin and not like a growing file
+#[cfg(unix)]
+fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::metadata(path) {
+        Ok(a) => a.file_type().is_fifo(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &str) -> bool {
+    false
+}
+
+/// Opens port selected in config, a serial port or an address like `tcp://host:port`.
+/// Baud rate and line settings are used by serial port and RFC 2217
+pub(crate) fn open(config: &Properties) -> io::Result<Box<dyn Source>> {
+    let port = match config.port.lock() {
+        Ok(a) => a.to_owned(),
+        Err(_) => return Err(io::Error::other("Can't get port")),
+    };
+    if let Some(address) = port.strip_prefix("tcp://") {
+        return Ok(Box::new(tcp::TcpSource::connect(address)?));
+    }
+    if let Some(address) = port.strip_prefix("rfc2217://") {
+        let line = match config.line.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => util::LineSettings::default(),
+        };
+        let bondrate = config.bondrate.load(Ordering::SeqCst);
+        return Ok(Box::new(rfc2217::Rfc2217Source::connect(
+            address, bondrate, line,
+        )?));
+    }
+    if let Some(address) = port.strip_prefix("mqtt://") {
+        let topics = match config.mqtt_topics.lock() {
+            Ok(a) => a.to_owned(),
+            Err(_) => vec!["#".to_owned()],
+        };
+        return Ok(Box::new(mqtt::MqttSource::connect(address, &topics)?));
+    }
+    if port == "stdin://" {
+        return Ok(Box::new(pipe::PipeSource::stdin()?));
+    }
+    if let Some(path) = port.strip_prefix("file://") {
+        if is_fifo(path) {
+            return Ok(Box::new(pipe::PipeSource::fifo(path)?));
+        }
+        return Ok(Box::new(file::FileSource::open(path)?));
+    }
+    if let Some(address) = port.strip_prefix("udp://") {
+        return Ok(Box::new(udp::UdpSource::bind(address)?));
+    }
+    Ok(Box::new(serial::SerialSource::new(port_util::open_port(
+        config,
+    )?)))
+}
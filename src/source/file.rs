@@ -0,0 +1,127 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! File which is growing, like a log, read from its end like `tail -f`
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use super::Source;
+
+pub(crate) struct FileSource {
+    path: PathBuf,
+    file: File,
+    pos: u64,
+}
+
+impl FileSource {
+    /// Opens file at path, only lines written after this are read
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let pos = file.seek(SeekFrom::End(0))?;
+        Ok(FileSource {
+            path: PathBuf::from(path),
+            file,
+            pos,
+        })
+    }
+
+    /// Whether file was truncated, or path is now another file after log rotation
+    fn replaced(&self) -> bool {
+        match (fs::metadata(&self.path), self.file.metadata()) {
+            (Ok(now), Ok(opened)) => !same_file(&now, &opened) || opened.len() < self.pos,
+            // file may be missing for a moment while it is rotated
+            _ => false,
+        }
+    }
+}
+
+/// Whether both are of same file, by device and inode
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Without inodes, a file of other length at path is taken as another file
+#[cfg(not(unix))]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.len() == b.len()
+}
+
+impl Source for FileSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.file.read(buf)? {
+            0 => {
+                if self.replaced() {
+                    self.file = File::open(&self.path)?;
+                    self.pos = 0;
+                }
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            len => {
+                self.pos += len as u64;
+                Ok(len)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn read_all(source: &mut FileSource) -> Vec<u8> {
+        let mut buf = [0u8; 64];
+        let mut data = Vec::new();
+        while let Ok(len) = source.read(&mut buf) {
+            data.extend_from_slice(&buf[..len]);
+        }
+        data
+    }
+
+    #[test]
+    fn rotated_and_truncated_files_are_read_again() {
+        let dir = std::env::temp_dir().join(format!("tarangam-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log");
+        fs::write(&path, "old\n").unwrap();
+        let mut source = FileSource::open(path.to_str().unwrap()).unwrap();
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"1\n")
+            .unwrap();
+        assert_eq!(read_all(&mut source), b"1\n");
+
+        // rotation, new file of same length at path
+        fs::rename(&path, dir.join("log.1")).unwrap();
+        fs::write(&path, "old\n1\n").unwrap();
+        assert_eq!(read_all(&mut source), b"");
+        assert_eq!(read_all(&mut source), b"old\n1\n");
+
+        // truncation of same file
+        fs::write(&path, "2\n").unwrap();
+        assert_eq!(read_all(&mut source), b"");
+        assert_eq!(read_all(&mut source), b"2\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
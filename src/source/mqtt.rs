@@ -0,0 +1,550 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! MQTT 3.1.1 subscriber, for devices sending to a broker like Mosquitto
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{tcp, Source};
+use crate::decoder::{mqtt::MqttDecoder, Decoder};
+
+const DEFAULT_PORT: u16 = 1883;
+
+/// Seconds after which broker may drop a silent client
+const KEEP_ALIVE: u16 = 60;
+
+/// Time for which broker may take to answer CONNECT and SUBSCRIBE
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Types of packets, in high 4 bits of first byte
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82; // low bits of SUBSCRIBE must be 0010
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xC0;
+const DISCONNECT: u8 = 0xE0;
+
+pub(crate) struct MqttSource {
+    stream: TcpStream,
+    raw: Vec<u8>, // bytes read, which may end in half of a packet
+    messages: VecDeque<(String, Vec<u8>)>, // topic and payload of messages for decoder
+    topic: Option<String>, // topic of message read last
+    notices: Vec<String>,
+    last_sent: Instant,
+}
+
+impl MqttSource {
+    /// Connects to broker at address like `host`, `host:port` or `user:password@host:port`
+    /// and subscribes to topics
+    pub(crate) fn connect(address: &str, topics: &[String]) -> io::Result<Self> {
+        let (login, host) = match address.rsplit_once('@') {
+            Some((login, host)) => (Some(login), host),
+            None => (None, address),
+        };
+        let host = if host.contains(':') && !host.ends_with(']') {
+            host.to_owned()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        let mut source = MqttSource {
+            stream: tcp::connect(&host)?,
+            raw: Vec::new(),
+            messages: VecDeque::new(),
+            topic: None,
+            notices: Vec::new(),
+            last_sent: Instant::now(),
+        };
+
+        source.send_packet(CONNECT, &connect_body(login))?;
+        match source.wait_for(CONNACK)?[..] {
+            [_, 0] => {}
+            [_, code] => {
+                let reason = match code {
+                    1 => "unacceptable protocol version",
+                    2 => "client identifier rejected",
+                    3 => "server unavailable",
+                    4 => "bad user name or password",
+                    5 => "not authorized",
+                    _ => "unknown reason",
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("Broker refused connection, {}", reason),
+                ));
+            }
+            _ => return Err(malformed()),
+        }
+
+        let mut body = vec![0, 1]; // packet identifier
+        for topic in topics {
+            push_string(&mut body, topic);
+            body.push(0); // QoS 0, plotting doesn't need every message
+        }
+        source.send_packet(SUBSCRIBE, &body)?;
+        let codes = source.wait_for(SUBACK)?;
+        for (topic, code) in topics.iter().zip(codes.iter().skip(2)) {
+            if *code == 0x80 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Broker refused topic {}", topic),
+                ));
+            }
+        }
+        Ok(source)
+    }
+
+    fn send_packet(&mut self, header: u8, body: &[u8]) -> io::Result<()> {
+        let mut bytes = vec![header];
+        // remaining length, 7 bits in each byte
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                bytes.push(byte | 0x80);
+            } else {
+                bytes.push(byte);
+                break;
+            }
+        }
+        bytes.extend_from_slice(body);
+        self.last_sent = Instant::now();
+        tcp::write_all(&mut self.stream, &bytes)
+    }
+
+    /// Reads bytes which have come, waits only while connecting, when stream blocks
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk)? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by broker",
+            )),
+            len => {
+                self.raw.extend_from_slice(&chunk[..len]);
+                Ok(())
+            }
+        }
+    }
+
+    /// First byte and body of packet, if whole packet has been read
+    fn take_packet(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let mut len = 0;
+        let mut index = 1;
+        loop {
+            let byte = match self.raw.get(index) {
+                Some(byte) => *byte,
+                None => return Ok(None),
+            };
+            len += ((byte & 0x7F) as usize) << (7 * (index - 1));
+            index += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if index > 4 {
+                return Err(malformed());
+            }
+        }
+        if self.raw.len() < index + len {
+            return Ok(None);
+        }
+        let header = self.raw[0];
+        let body = self.raw[index..index + len].to_vec();
+        self.raw.drain(..index + len);
+        Ok(Some((header, body)))
+    }
+
+    /// Keeps message of PUBLISH, other packets need nothing
+    fn handle(&mut self, header: u8, body: Vec<u8>) -> io::Result<()> {
+        if header & 0xF0 != PUBLISH {
+            return Ok(());
+        }
+        if body.len() < 2 {
+            return Err(malformed());
+        }
+        let topic_end = 2 + u16::from_be_bytes([body[0], body[1]]) as usize;
+        // packet identifier comes only with QoS 1 and 2
+        let payload_start = if (header >> 1) & 0x03 > 0 {
+            topic_end + 2
+        } else {
+            topic_end
+        };
+        if body.len() < payload_start {
+            return Err(malformed());
+        }
+        let topic = String::from_utf8_lossy(&body[2..topic_end]).into_owned();
+        self.messages
+            .push_back((topic, body[payload_start..].to_vec()));
+        Ok(())
+    }
+
+    /// Waits for packet while connecting, messages coming before it are kept
+    fn wait_for(&mut self, kind: u8) -> io::Result<Vec<u8>> {
+        let result = self.read_until(kind);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+
+    /// Reads with blocking reads till packet of kind comes or time is over
+    fn read_until(&mut self, kind: u8) -> io::Result<Vec<u8>> {
+        let start = Instant::now();
+        loop {
+            while let Some((header, body)) = self.take_packet()? {
+                if header & 0xF0 == kind & 0xF0 {
+                    return Ok(body);
+                }
+                self.handle(header, body)?;
+            }
+            let left = HANDSHAKE_TIMEOUT.saturating_sub(start.elapsed());
+            if left.is_zero() {
+                return Err(broker_silent());
+            }
+            // packets sent while handling make stream nonblocking again
+            self.stream.set_nonblocking(false)?;
+            self.stream.set_read_timeout(Some(left))?;
+            match self.fill() {
+                Ok(_) => {}
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(broker_silent())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for MqttSource {
+    fn drop(&mut self) {
+        self.send_packet(DISCONNECT, &[]).ok();
+    }
+}
+
+/// Appends string with its length in 2 bytes
+fn push_string(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+}
+
+fn broker_silent() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "Broker didn't answer")
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed packet from broker")
+}
+
+/// Body of CONNECT with clean session, login is like `user` or `user:password`
+fn connect_body(login: Option<&str>) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    let mut payload = Vec::new();
+    // every connection needs its own identifier, or broker drops the older one
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|a| a.subsec_nanos())
+        .unwrap_or(0);
+    push_string(
+        &mut payload,
+        &format!("tarangam-{}-{}", std::process::id(), nanos),
+    );
+    if let Some(login) = login {
+        let (user, password) = match login.split_once(':') {
+            Some((user, password)) => (user, Some(password)),
+            None => (login, None),
+        };
+        flags |= 0x80;
+        push_string(&mut payload, user);
+        if let Some(password) = password {
+            flags |= 0x40;
+            push_string(&mut payload, password);
+        }
+    }
+
+    let mut body = Vec::new();
+    push_string(&mut body, "MQTT");
+    body.push(4); // level of protocol 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+    body.extend(payload);
+    body
+}
+
+impl Source for MqttSource {
+    /// Gives one message at a time
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.last_sent.elapsed() >= Duration::from_secs(KEEP_ALIVE as u64 / 2) {
+            self.send_packet(PINGREQ, &[])?;
+        }
+        if self.messages.is_empty() {
+            self.fill()?;
+            while let Some((header, body)) = self.take_packet()? {
+                self.handle(header, body)?;
+            }
+        }
+        while let Some((topic, payload)) = self.messages.pop_front() {
+            // a cut payload would be plotted wrong, so it is left
+            if payload.len() > buf.len() {
+                self.notices.push(format!(
+                    "Message of {} has {} bytes, more than {} which can be read",
+                    topic,
+                    payload.len(),
+                    buf.len()
+                ));
+                continue;
+            }
+            buf[..payload.len()].copy_from_slice(&payload);
+            self.topic = Some(topic);
+            return Ok(payload.len());
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    /// Topic of message read last, lines of message are named after it
+    fn origin(&self) -> Option<String> {
+        self.topic.clone()
+    }
+
+    fn notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notices)
+    }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+
+    fn decoder(&self) -> Option<Box<dyn Decoder>> {
+        Some(Box::new(MqttDecoder))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Source over a loopback connection, for tests of packets put in raw by hand
+    fn source() -> (MqttSource, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        let source = MqttSource {
+            stream,
+            raw: Vec::new(),
+            messages: VecDeque::new(),
+            topic: None,
+            notices: Vec::new(),
+            last_sent: Instant::now(),
+        };
+        (source, peer)
+    }
+
+    /// Reads a whole packet, for stand-in broker
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let mut len = 0;
+        let mut shift = 0;
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            len += ((byte[0] & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    /// Stand-in broker answering CONNECT with code, and every topic with suback.
+    /// After subscribing it publishes `24.5` on `home/temp`
+    fn broker(code: u8, suback: u8) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (header, _) = read_packet(&mut stream);
+            assert_eq!(header, CONNECT);
+            stream.write_all(&[CONNACK, 2, 0, code]).unwrap();
+            if code != 0 {
+                return;
+            }
+            let (header, body) = read_packet(&mut stream);
+            assert_eq!(header, SUBSCRIBE);
+            let topic_len = u16::from_be_bytes([body[2], body[3]]) as usize;
+            assert_eq!(&body[4..4 + topic_len], b"home/#");
+            stream
+                .write_all(&[SUBACK, 3, body[0], body[1], suback])
+                .unwrap();
+            if suback == 0x80 {
+                return;
+            }
+            let mut publish = vec![PUBLISH, 15];
+            push_string(&mut publish, "home/temp");
+            publish.extend_from_slice(b"24.5");
+            stream.write_all(&publish).unwrap();
+            // keeps connection till DISCONNECT of client
+            read_packet(&mut stream);
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn packet_with_long_length() {
+        let (mut source, _peer) = source();
+        source.raw = vec![PUBLISH, 0xC8, 0x01];
+        source.raw.extend(vec![7u8; 200]);
+        source.raw.push(PINGREQ);
+        let (header, body) = source.take_packet().unwrap().unwrap();
+        assert_eq!(header, PUBLISH);
+        assert_eq!(body, vec![7u8; 200]);
+        assert_eq!(source.raw, vec![PINGREQ]);
+    }
+
+    #[test]
+    fn half_read_packet_waits() {
+        let (mut source, _peer) = source();
+        source.raw = vec![PUBLISH, 0x80];
+        assert!(source.take_packet().unwrap().is_none());
+        source.raw = vec![PUBLISH, 4, 0, 1];
+        assert!(source.take_packet().unwrap().is_none());
+        assert_eq!(source.raw.len(), 4);
+        source.raw.extend_from_slice(b"a1");
+        assert_eq!(
+            source.take_packet().unwrap(),
+            Some((PUBLISH, vec![0, 1, b'a', b'1']))
+        );
+    }
+
+    #[test]
+    fn fifth_length_byte_is_malformed() {
+        let (mut source, _peer) = source();
+        source.raw = vec![PUBLISH, 0x80, 0x80, 0x80, 0x80, 0x01];
+        let error = source.take_packet().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn packet_identifier_is_skipped() {
+        let (mut source, _peer) = source();
+        // QoS 1 has packet identifier after topic
+        source
+            .handle(PUBLISH | 0x02, vec![0, 2, b'a', b'b', 0, 5, b'1'])
+            .unwrap();
+        // QoS 0 has none
+        source
+            .handle(PUBLISH, vec![0, 2, b'a', b'b', b'2'])
+            .unwrap();
+        assert_eq!(
+            Vec::from(source.messages.clone()),
+            vec![
+                ("ab".to_owned(), b"1".to_vec()),
+                ("ab".to_owned(), b"2".to_vec())
+            ]
+        );
+        assert!(source
+            .handle(PUBLISH | 0x02, vec![0, 2, b'a', b'b', 0])
+            .is_err());
+    }
+
+    #[test]
+    fn too_long_message_is_left() {
+        let (mut source, _peer) = source();
+        source
+            .messages
+            .push_back(("big".to_owned(), vec![b'1'; 10]));
+        source
+            .messages
+            .push_back(("small".to_owned(), b"2".to_vec()));
+        let mut buf = [0u8; 4];
+        assert_eq!(source.read(&mut buf).unwrap(), 1);
+        assert_eq!(source.origin(), Some("small".to_owned()));
+        assert_eq!(source.notices().len(), 1);
+    }
+
+    #[test]
+    fn login_in_connect() {
+        // protocol name, level, flags, keep alive, then client identifier
+        let body = connect_body(None);
+        assert_eq!(&body[..7], &[0, 4, b'M', b'Q', b'T', b'T', 4]);
+        assert_eq!(body[7], 0x02);
+        assert_eq!(&body[8..10], &KEEP_ALIVE.to_be_bytes());
+
+        let body = connect_body(Some("ravi"));
+        assert_eq!(body[7], 0x82);
+        assert!(body.ends_with(&[0, 4, b'r', b'a', b'v', b'i']));
+
+        let body = connect_body(Some("ravi:a:b"));
+        assert_eq!(body[7], 0xC2);
+        assert!(body.ends_with(&[0, 4, b'r', b'a', b'v', b'i', 0, 3, b'a', b':', b'b']));
+    }
+
+    #[test]
+    fn subscribes_and_reads_topic() {
+        let (address, handle) = broker(0, 0);
+        let mut source = MqttSource::connect(&address, &["home/#".to_owned()]).unwrap();
+        let mut buf = [0u8; 64];
+        let start = Instant::now();
+        let len = loop {
+            match source.read(&mut buf) {
+                Ok(len) => break len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(start.elapsed() < HANDSHAKE_TIMEOUT);
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert_eq!(&buf[..len], b"24.5");
+        assert_eq!(source.origin(), Some("home/temp".to_owned()));
+        drop(source);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn refused_connection() {
+        let (address, handle) = broker(5, 0);
+        let error = match MqttSource::connect(&address, &["home/#".to_owned()]) {
+            Ok(_) => panic!("connected though broker refused"),
+            Err(e) => e,
+        };
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(error.to_string().contains("not authorized"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn refused_topic() {
+        let (address, handle) = broker(0, 0x80);
+        let error = match MqttSource::connect(&address, &["home/#".to_owned()]) {
+            Ok(_) => panic!("subscribed though broker refused topic"),
+            Err(e) => e,
+        };
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("home/#"));
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! Pipes, stdin of Tarangam or a named pipe (FIFO), for output of other programs
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use super::Source;
+
+/// Chunks which may wait for a source, older ones are read before reader thread gets more
+const PIPE_CHUNKS: usize = 64;
+
+type Chunks = mpsc::Receiver<Vec<u8>>;
+
+/// Pipe opened so far. A pipe can't be read without waiting, so it is read by a
+/// thread of its own for whole run, as the thread can't be stopped
+struct Pipe {
+    name: String,
+    chunks: Option<Chunks>,    // None while a source has it
+    attached: Arc<AtomicBool>, // chunks are thrown away by thread when false
+}
+
+static PIPES: Mutex<Vec<Pipe>> = Mutex::new(Vec::new());
+
+pub(crate) struct PipeSource {
+    name: String,
+    chunks: Option<Chunks>, // given back to PIPES when closed
+    attached: Arc<AtomicBool>,
+}
+
+impl PipeSource {
+    pub(crate) fn stdin() -> io::Result<Self> {
+        PipeSource::open("stdin", None)
+    }
+
+    /// Named pipe at path, which is opened again when its writer goes
+    pub(crate) fn fifo(path: &str) -> io::Result<Self> {
+        PipeSource::open(path, Some(PathBuf::from(path)))
+    }
+
+    fn open(name: &str, path: Option<PathBuf>) -> io::Result<Self> {
+        let mut pipes = match PIPES.lock() {
+            Ok(a) => a,
+            Err(_) => return Err(io::Error::other("Can't get pipes")),
+        };
+        let (chunks, attached) = match pipes.iter_mut().find(|a| a.name == name) {
+            Some(pipe) => {
+                let mut chunks = pipe.chunks.take().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is read by another connection", name),
+                    )
+                })?;
+                // stdin which has ended stays ended, a named pipe can get a writer again
+                if !drain(&chunks) && path.is_some() {
+                    chunks = spawn_reader(path, pipe.attached.clone());
+                }
+                (chunks, pipe.attached.clone())
+            }
+            None => {
+                let attached = Arc::new(AtomicBool::new(false));
+                pipes.push(Pipe {
+                    name: name.to_owned(),
+                    chunks: None,
+                    attached: attached.clone(),
+                });
+                (spawn_reader(path, attached.clone()), attached)
+            }
+        };
+        attached.store(true, Ordering::SeqCst);
+        Ok(PipeSource {
+            name: name.to_owned(),
+            chunks: Some(chunks),
+            attached,
+        })
+    }
+}
+
+impl Drop for PipeSource {
+    fn drop(&mut self) {
+        self.attached.store(false, Ordering::SeqCst);
+        if let Some(chunks) = &self.chunks {
+            // frees thread if it waits for room
+            drain(chunks);
+        }
+        if let Ok(mut pipes) = PIPES.lock() {
+            if let Some(pipe) = pipes.iter_mut().find(|a| a.name == self.name) {
+                pipe.chunks = self.chunks.take();
+            }
+        }
+    }
+}
+
+/// Throws away chunks which have come, false if reader thread has ended
+fn drain(chunks: &Chunks) -> bool {
+    loop {
+        match chunks.try_recv() {
+            Ok(_) => {}
+            Err(mpsc::TryRecvError::Empty) => return true,
+            Err(mpsc::TryRecvError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Starts thread reading stdin, or named pipe at path
+fn spawn_reader(path: Option<PathBuf>, attached: Arc<AtomicBool>) -> Chunks {
+    let (sender, chunks) = mpsc::sync_channel(PIPE_CHUNKS);
+    std::thread::spawn(move || match path {
+        None => {
+            let stdin = io::stdin();
+            forward(stdin.lock(), &sender, &attached);
+        }
+        // opening waits for a writer, and writers come and go
+        Some(path) => {
+            while let Ok(file) = File::open(&path) {
+                if !forward(file, &sender, &attached) {
+                    break;
+                }
+            }
+        }
+    });
+    chunks
+}
+
+/// Sends chunks of reader till its end, while a source is attached, false if
+/// they are not taken anymore
+fn forward(
+    mut reader: impl Read,
+    sender: &mpsc::SyncSender<Vec<u8>>,
+    attached: &AtomicBool,
+) -> bool {
+    let mut chunk = [0u8; 4096];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return true,
+            Ok(len) => {
+                if !attached.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if sender.send(chunk[..len].to_vec()).is_err() {
+                    return false;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return true,
+        }
+    }
+}
+
+impl Source for PipeSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunks = match &self.chunks {
+            Some(a) => a,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+        match chunks.try_recv() {
+            Ok(chunk) => {
+                let len = chunk.len().min(buf.len());
+                buf[..len].copy_from_slice(&chunk[..len]);
+                Ok(len)
+            }
+            Err(mpsc::TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "End of input"))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    fn mkfifo(path: &std::path::Path) {
+        let status = std::process::Command::new("mkfifo")
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fn writer(path: &std::path::Path) -> File {
+        OpenOptions::new().write(true).open(path).unwrap()
+    }
+
+    /// Reads till a line ends, or gives error of source
+    fn read_line(source: &mut PipeSource) -> io::Result<Vec<u8>> {
+        let start = Instant::now();
+        let mut line = Vec::new();
+        let mut buf = [0u8; 64];
+        while !line.ends_with(b"\n") {
+            match source.read(&mut buf) {
+                Ok(len) => line.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(start.elapsed() < Duration::from_secs(3), "no line came");
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(line)
+    }
+
+    #[test]
+    fn named_pipe() {
+        let dir = std::env::temp_dir().join(format!("tarangam-pipe-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fifo");
+        mkfifo(&path);
+        let name = path.to_str().unwrap();
+
+        let mut source = PipeSource::fifo(name).unwrap();
+        assert_eq!(
+            PipeSource::fifo(name).err().map(|e| e.kind()),
+            Some(io::ErrorKind::AddrInUse)
+        );
+        let mut write = writer(&path);
+        write.write_all(b"a\n").unwrap();
+        assert_eq!(read_line(&mut source).unwrap(), b"a\n");
+
+        // writer goes and comes again
+        drop(write);
+        let mut write = writer(&path);
+        write.write_all(b"b\n").unwrap();
+        assert_eq!(read_line(&mut source).unwrap(), b"b\n");
+
+        // lines written while no source is attached are thrown away
+        drop(source);
+        write.write_all(b"stale\n").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let mut source = PipeSource::fifo(name).unwrap();
+        write.write_all(b"fresh\n").unwrap();
+        assert_eq!(read_line(&mut source).unwrap(), b"fresh\n");
+
+        // reader thread ends when pipe is gone, and is started again on next open
+        fs::remove_file(&path).unwrap();
+        drop(write);
+        let error = read_line(&mut source).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        drop(source);
+        mkfifo(&path);
+        let mut source = PipeSource::fifo(name).unwrap();
+        let mut write = writer(&path);
+        write.write_all(b"c\n").unwrap();
+        assert_eq!(read_line(&mut source).unwrap(), b"c\n");
+
+        drop(write);
+        drop(source);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,471 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! RFC 2217 client, serial port of a terminal server controlled over Telnet
+
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use super::{tcp, Source};
+use crate::util::{LineSettings, ModemLines};
+
+// Telnet commands
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// Telnet options
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// Commands of COM-PORT-OPTION, server answers with command + 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const SERVER_OFFSET: u8 = 100;
+
+/// Time for which server may take to answer while setting up port
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where reading of Telnet stream is, as commands may be split between reads
+#[derive(Clone, Copy)]
+enum Telnet {
+    Data,
+    Iac,
+    Command(u8),
+    Sub,
+    SubIac,
+}
+
+pub(crate) struct Rfc2217Source {
+    stream: TcpStream,
+    telnet: Telnet,
+    sub: Vec<u8>,                 // subnegotiation being read
+    raw: Vec<u8>,                 // bytes read, with Telnet commands
+    pending: Vec<u8>,             // data which came while setting up port
+    modem: Option<ModemLines>,    // last levels told by server
+    com_port: Option<bool>,       // whether server agreed to COM-PORT-OPTION, None till it answers
+    expected: Vec<(u8, Vec<u8>)>, // settings sent while connecting, whose answer has not come
+    notices: Vec<String>,         // answers not same as asked, for status bar
+}
+
+impl Rfc2217Source {
+    /// Connects to terminal server at `host:port` and sets up its port like a local one
+    pub(crate) fn connect(address: &str, bondrate: u32, line: LineSettings) -> io::Result<Self> {
+        let mut source = Rfc2217Source {
+            stream: tcp::connect(address)?,
+            telnet: Telnet::Data,
+            sub: Vec::new(),
+            raw: Vec::new(),
+            pending: Vec::new(),
+            modem: None,
+            com_port: None,
+            expected: Vec::new(),
+            notices: Vec::new(),
+        };
+        #[rustfmt::skip]
+        source.write_raw(&[
+            IAC, WILL, COM_PORT_OPTION,
+            IAC, WILL, BINARY,
+            IAC, DO, BINARY,
+            IAC, DO, SGA,
+        ])?;
+        source.wait_for(|a| a.com_port.is_some())?;
+        match source.com_port {
+            Some(true) => {}
+            Some(false) => {
+                return Err(io::Error::other(
+                    "Server refused COM-PORT-OPTION, it may not support RFC 2217",
+                ))
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Server didn't answer COM-PORT-OPTION",
+                ))
+            }
+        }
+
+        source.setting(SET_BAUDRATE, &bondrate.to_be_bytes())?;
+        source.setting(
+            SET_DATASIZE,
+            &[match line.data_bits {
+                serialport::DataBits::Five => 5,
+                serialport::DataBits::Six => 6,
+                serialport::DataBits::Seven => 7,
+                serialport::DataBits::Eight => 8,
+            }],
+        )?;
+        source.setting(
+            SET_PARITY,
+            &[match line.parity {
+                serialport::Parity::None => 1,
+                serialport::Parity::Odd => 2,
+                serialport::Parity::Even => 3,
+            }],
+        )?;
+        source.setting(
+            SET_STOPSIZE,
+            &[match line.stop_bits {
+                serialport::StopBits::One => 1,
+                serialport::StopBits::Two => 2,
+            }],
+        )?;
+        source.setting(
+            SET_CONTROL,
+            &[match line.flow_control {
+                serialport::FlowControl::None => 1,
+                serialport::FlowControl::Software => 2,
+                serialport::FlowControl::Hardware => 3,
+            }],
+        )?;
+        if !source.wait_for(|a| a.expected.is_empty())? {
+            source
+                .notices
+                .push("RFC 2217 server didn't confirm settings of port".to_owned());
+        }
+        // answers coming later, or of DTR, RTS and break, are not checked
+        source.expected.clear();
+        Ok(source)
+    }
+
+    /// Reads from server till done is true or it takes too long, data is kept for
+    /// reading later. Returns whether done is true
+    fn wait_for(&mut self, done: fn(&Self) -> bool) -> io::Result<bool> {
+        let start = Instant::now();
+        let mut buf = [0u8; 1024];
+        while !done(self) {
+            let left = HANDSHAKE_TIMEOUT.saturating_sub(start.elapsed());
+            if left.is_zero() {
+                break;
+            }
+            // answers written while reading make stream nonblocking again
+            self.stream.set_nonblocking(false)?;
+            self.stream.set_read_timeout(Some(left))?;
+            match self.read_telnet(&mut buf) {
+                Ok(len) => self.pending.extend_from_slice(&buf[..len]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                Err(e) => {
+                    self.stream.set_nonblocking(true)?;
+                    return Err(e);
+                }
+            }
+        }
+        self.stream.set_nonblocking(true)?;
+        Ok(done(self))
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        tcp::write_all(&mut self.stream, bytes)
+    }
+
+    /// Sends command of COM-PORT-OPTION with its value
+    fn command(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        let mut bytes = vec![IAC, SB, COM_PORT_OPTION, command];
+        bytes.extend(escape(value));
+        bytes.extend_from_slice(&[IAC, SE]);
+        self.write_raw(&bytes)
+    }
+
+    /// Sends setting while connecting, server answers with value it has set
+    fn setting(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        self.expected.push((command, value.to_vec()));
+        self.command(command, value)
+    }
+
+    /// Takes in subnegotiation sent by server, levels of modem lines and answers of commands
+    fn subnegotiation(&mut self) {
+        let (command, value) = match &self.sub[..] {
+            [COM_PORT_OPTION, command, value @ ..] => (*command, value.to_vec()),
+            _ => return,
+        };
+        if command == NOTIFY_MODEMSTATE + SERVER_OFFSET {
+            if let Some(state) = value.first() {
+                self.modem = Some(ModemLines {
+                    cts: state & 0x10 != 0,
+                    dsr: state & 0x20 != 0,
+                    ri: state & 0x40 != 0,
+                    cd: state & 0x80 != 0,
+                });
+            }
+            return;
+        }
+        let asked = match self
+            .expected
+            .iter()
+            .position(|(sent, _)| *sent + SERVER_OFFSET == command)
+        {
+            Some(index) => self.expected.remove(index).1,
+            None => return,
+        };
+        if value != asked {
+            self.notices.push(format!(
+                "RFC 2217 server set {} to {} instead of {}",
+                setting_name(command - SERVER_OFFSET),
+                setting_value(&value),
+                setting_value(&asked)
+            ));
+        }
+    }
+
+    /// Reads bytes from server and gives data after taking out Telnet commands
+    fn read_telnet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.raw.resize(buf.len(), 0);
+        let len = match self.stream.read(&mut self.raw) {
+            Ok(0) if !buf.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed by server",
+                ))
+            }
+            result => result?,
+        };
+
+        let mut out = 0;
+        let mut answers = Vec::new();
+        for index in 0..len {
+            let byte = self.raw[index];
+            self.telnet = match (self.telnet, byte) {
+                (Telnet::Data, IAC) => Telnet::Iac,
+                (Telnet::Data, _) | (Telnet::Iac, IAC) => {
+                    buf[out] = byte;
+                    out += 1;
+                    Telnet::Data
+                }
+                (Telnet::Iac, SB) => {
+                    self.sub.clear();
+                    Telnet::Sub
+                }
+                (Telnet::Iac, DO | DONT | WILL | WONT) => Telnet::Command(byte),
+                // NOP, GA and others have nothing to do with port
+                (Telnet::Iac, _) => Telnet::Data,
+                (Telnet::Command(command), _) => {
+                    if byte == COM_PORT_OPTION && (command == DO || command == DONT) {
+                        self.com_port = Some(command == DO);
+                    }
+                    answers.extend(answer(command, byte));
+                    Telnet::Data
+                }
+                (Telnet::Sub, IAC) => Telnet::SubIac,
+                (Telnet::SubIac, SE) => {
+                    self.subnegotiation();
+                    Telnet::Data
+                }
+                (Telnet::Sub | Telnet::SubIac, _) => {
+                    self.sub.push(byte);
+                    Telnet::Sub
+                }
+            };
+        }
+        if !answers.is_empty() {
+            self.write_raw(&answers)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Name of setting changed by command, for messages
+fn setting_name(command: u8) -> &'static str {
+    match command {
+        SET_BAUDRATE => "baud rate",
+        SET_DATASIZE => "data size",
+        SET_PARITY => "parity",
+        SET_STOPSIZE => "stop size",
+        _ => "control",
+    }
+}
+
+/// Value of setting, baud rate has 4 bytes and others have one
+fn setting_value(value: &[u8]) -> u32 {
+    value.iter().fold(0, |val, byte| (val << 8) | *byte as u32)
+}
+
+/// Doubles IAC, so that it is not taken as a command
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for byte in bytes {
+        if *byte == IAC {
+            escaped.push(IAC);
+        }
+        escaped.push(*byte);
+    }
+    escaped
+}
+
+/// Answer to option asked by server, options other than ours are refused
+fn answer(command: u8, option: u8) -> Vec<u8> {
+    match command {
+        DO if option != COM_PORT_OPTION && option != BINARY => vec![IAC, WONT, option],
+        WILL if option != BINARY && option != SGA => vec![IAC, DONT, option],
+        _ => Vec::new(),
+    }
+}
+
+impl Source for Rfc2217Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            let len = self.pending.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            return Ok(len);
+        }
+        self.read_telnet(buf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_raw(&escape(bytes))
+    }
+
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if level { 8 } else { 9 }])
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if level { 11 } else { 12 }])
+    }
+
+    fn set_break(&mut self, on: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if on { 5 } else { 6 }])
+    }
+
+    fn modem_lines(&mut self) -> Option<ModemLines> {
+        self.modem
+    }
+
+    fn notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Stand-in terminal server, answers COM-PORT-OPTION with `agree` and gives
+    /// back settings asked, with baud rate changed to 9600, then sends `hello`
+    fn server(agree: bool) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut got = Vec::new();
+            let mut buf = [0u8; 256];
+            while !got.windows(3).any(|a| a == [IAC, WILL, COM_PORT_OPTION]) {
+                let len = stream.read(&mut buf).unwrap();
+                got.extend_from_slice(&buf[..len]);
+            }
+            let reply = if agree { DO } else { DONT };
+            stream.write_all(&[IAC, reply, COM_PORT_OPTION]).unwrap();
+            if !agree {
+                return;
+            }
+
+            let mut answered = 0;
+            let mut start = 0;
+            while answered < 5 {
+                let len = stream.read(&mut buf).unwrap();
+                got.extend_from_slice(&buf[..len]);
+                while let Some(begin) = got[start..]
+                    .windows(3)
+                    .position(|a| a == [IAC, SB, COM_PORT_OPTION])
+                {
+                    let begin = start + begin;
+                    let end = match got[begin..].windows(2).position(|a| a == [IAC, SE]) {
+                        Some(end) => begin + end,
+                        None => break,
+                    };
+                    let command = got[begin + 3];
+                    let mut value = got[begin + 4..end].to_vec();
+                    if command == SET_BAUDRATE {
+                        value = 9600u32.to_be_bytes().to_vec();
+                    }
+                    let mut answer = vec![IAC, SB, COM_PORT_OPTION, command + SERVER_OFFSET];
+                    answer.extend(value);
+                    answer.extend_from_slice(&[IAC, SE]);
+                    stream.write_all(&answer).unwrap();
+                    answered += 1;
+                    start = end + 2;
+                }
+            }
+            stream.write_all(b"hello\n").unwrap();
+            // keeps connection till client has read
+            let _ = stream.read(&mut buf);
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn settings_not_made_are_told() {
+        let (address, handle) = server(true);
+        let mut source = Rfc2217Source::connect(&address, 115200, LineSettings::default()).unwrap();
+        assert_eq!(
+            source.notices(),
+            vec!["RFC 2217 server set baud rate to 9600 instead of 115200".to_owned()]
+        );
+        assert!(source.notices().is_empty());
+        // stand-in server doesn't answer control commands, nothing waits for them
+        source.set_dtr(false).unwrap();
+        source.set_rts(false).unwrap();
+        assert!(source.expected.is_empty());
+
+        let mut data = Vec::new();
+        let start = Instant::now();
+        let mut buf = [0u8; 64];
+        while !data.ends_with(b"\n") && start.elapsed() < HANDSHAKE_TIMEOUT {
+            match source.read(&mut buf) {
+                Ok(len) => data.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(data, b"hello\n");
+        drop(source);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn refused_com_port_option_fails() {
+        let (address, handle) = server(false);
+        let error = match Rfc2217Source::connect(&address, 9600, LineSettings::default()) {
+            Ok(_) => panic!("connected to server without COM-PORT-OPTION"),
+            Err(e) => e,
+        };
+        assert!(error.to_string().contains("refused COM-PORT-OPTION"));
+        handle.join().unwrap();
+    }
+}
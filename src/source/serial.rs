@@ -0,0 +1,78 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! Serial port, which is a device attached to this computer
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use super::Source;
+use crate::util::ModemLines;
+
+/// Time for which writing may wait for device
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub(crate) struct SerialSource {
+    port: Box<dyn serialport::SerialPort>,
+}
+
+impl SerialSource {
+    pub(crate) fn new(port: Box<dyn serialport::SerialPort>) -> Self {
+        SerialSource { port }
+    }
+}
+
+impl Source for SerialSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // reads don't wait, but writes must wait for space in buffer of driver
+        self.port.set_timeout(WRITE_TIMEOUT).ok();
+        let written = self.port.write_all(bytes);
+        self.port.set_timeout(Duration::from_millis(0)).ok();
+        written
+    }
+
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_data_terminal_ready(level)?)
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_request_to_send(level)?)
+    }
+
+    fn set_break(&mut self, on: bool) -> io::Result<()> {
+        if on {
+            Ok(self.port.set_break()?)
+        } else {
+            Ok(self.port.clear_break()?)
+        }
+    }
+
+    fn modem_lines(&mut self) -> Option<ModemLines> {
+        match (
+            self.port.read_clear_to_send(),
+            self.port.read_data_set_ready(),
+            self.port.read_ring_indicator(),
+            self.port.read_carrier_detect(),
+        ) {
+            (Ok(cts), Ok(dsr), Ok(ri), Ok(cd)) => Some(ModemLines { cts, dsr, ri, cd }),
+            _ => None,
+        }
+    }
+}
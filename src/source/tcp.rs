@@ -0,0 +1,86 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! TCP client, for boards bridged over WiFi and ports shared by ser2net in raw mode
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::Source;
+
+/// Time for which connecting may wait for server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Time for which writing may wait for server
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub(crate) struct TcpSource {
+    stream: TcpStream,
+}
+
+impl TcpSource {
+    pub(crate) fn connect(address: &str) -> io::Result<Self> {
+        Ok(TcpSource {
+            stream: connect(address)?,
+        })
+    }
+}
+
+/// Connects to address like `host:port`, every address of host is tried.
+/// Stream doesn't wait on reading
+pub(super) fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No address found for {}", address),
+    );
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true).ok();
+                return Ok(stream);
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Writes all bytes to stream which doesn't wait on reading
+pub(super) fn write_all(stream: &mut TcpStream, bytes: &[u8]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let written = stream.write_all(bytes);
+    stream.set_nonblocking(true)?;
+    written
+}
+
+impl Source for TcpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            Ok(0) if !buf.is_empty() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by server",
+            )),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        write_all(&mut self.stream, bytes)
+    }
+}
@@ -0,0 +1,59 @@
+/*
+    This file is part of Tarangam.
+
+    Tarangam is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Tarangam is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Tarangam.  If not, see <https://www.gnu.org/licenses/>
+*/
+//! UDP listener, for many sensor nodes sending to one place
+
+use std::io;
+use std::net::{IpAddr, UdpSocket};
+
+use super::Source;
+
+pub(crate) struct UdpSource {
+    socket: UdpSocket,
+    origin: Option<IpAddr>, // sender of last datagram
+}
+
+impl UdpSource {
+    /// Listens on address like `0.0.0.0:5005`, or on every interface if only port is given
+    pub(crate) fn bind(address: &str) -> io::Result<Self> {
+        let socket = match address.parse::<u16>() {
+            Ok(port) => UdpSocket::bind(("0.0.0.0", port))?,
+            Err(_) => UdpSocket::bind(address)?,
+        };
+        socket.set_nonblocking(true)?;
+        Ok(UdpSource {
+            socket,
+            origin: None,
+        })
+    }
+}
+
+impl Source for UdpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (len, from) = self.socket.recv_from(buf)?;
+        self.origin = Some(from.ip());
+        Ok(len)
+    }
+
+    /// Address of sender without port, as port changes when node restarts
+    fn origin(&self) -> Option<String> {
+        self.origin.map(|ip| ip.to_string())
+    }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+}
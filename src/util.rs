@@ -17,7 +17,12 @@
 
 //! Feel free to see through codes. Application is not written to be used as a library for other app. :)
 
-use std::sync::{atomic::*, Mutex};
+use std::collections::HashMap;
+use std::sync::{atomic::*, Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::decoder::binary::BinaryFormat;
+use crate::rules::Rule;
 
 /// Status of Serial reading
 #[derive(Debug, Clone, Copy)]
@@ -30,9 +35,306 @@ pub(crate) enum Status {
 
 #[derive(Debug)]
 pub(crate) struct Properties {
+    pub(crate) name: String, // prefix of lines of this connection, empty for connection of toolbar
+    pub(crate) closed: AtomicBool, // connection is removed, its thread ends
+    pub(crate) started: Arc<Mutex<SystemTime>>, // start of session, shared by all connections
     pub(crate) bondrate: AtomicU32,
     pub(crate) port: Mutex<String>,
     pub(crate) status: Mutex<Status>,
+    pub(crate) line: Mutex<LineSettings>,
+    pub(crate) dtr: AtomicBool,
+    pub(crate) rts: AtomicBool,
+    pub(crate) reset_on_connect: Mutex<ResetOnConnect>,
+    pub(crate) break_ms: AtomicU32,
+    pub(crate) auto_reconnect: AtomicBool,
+    pub(crate) decoder: Mutex<DecoderKind>,
+    pub(crate) csv_delimiter: Mutex<char>,
+    pub(crate) binary_format: Mutex<BinaryFormat>,
+    pub(crate) x_axis: Mutex<XAxis>,
+    pub(crate) rules: Mutex<Vec<Rule>>,
+    pub(crate) encoding: Mutex<Encoding>,
+    pub(crate) terminator: Mutex<Terminator>,
+    pub(crate) idle_flush: AtomicU32, // milliseconds of silence after which line is shown, 0 for never
+    pub(crate) aliases: Mutex<HashMap<String, String>>, // names of UDP senders by address
+    pub(crate) mqtt_topics: Mutex<Vec<String>>, // filters subscribed on MQTT broker
+}
+
+/// What is used as value on pankti
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum XAxis {
+    Sankhya,      // counter of lines having points
+    DeviceMillis, // key `t` sent by device in milliseconds
+    DeviceMicros, // key `t` sent by device in microseconds
+    HostClock,    // time of arrival on this computer, as time of day
+    HostRelative, // time of arrival on this computer, in seconds since start
+}
+
+impl XAxis {
+    /// Get x axis from id of item in combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "sankhya" => Some(XAxis::Sankhya),
+            "device_ms" => Some(XAxis::DeviceMillis),
+            "device_us" => Some(XAxis::DeviceMicros),
+            "host_clock" => Some(XAxis::HostClock),
+            "host_relative" => Some(XAxis::HostRelative),
+            _ => None,
+        }
+    }
+}
+
+/// Grammar of text coming from serial port
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DecoderKind {
+    Tarangam, // `#a=1 b=2` lines
+    Arduino,  // lines of Arduino IDE Serial Plotter
+    Csv,      // CSV with header row
+    Json,     // JSON object in each line
+    Binary,   // COBS or SLIP frames
+    Mavlink,  // MAVLink v1 and v2 messages
+    Nmea,     // NMEA 0183 sentences of GPS
+}
+
+impl DecoderKind {
+    /// Get kind from id of item in decoder combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "tarangam" => Some(DecoderKind::Tarangam),
+            "arduino" => Some(DecoderKind::Arduino),
+            "csv" => Some(DecoderKind::Csv),
+            "json" => Some(DecoderKind::Json),
+            "binary" => Some(DecoderKind::Binary),
+            "mavlink" => Some(DecoderKind::Mavlink),
+            "nmea" => Some(DecoderKind::Nmea),
+            _ => None,
+        }
+    }
+}
+
+/// How bytes of a line are turned into text
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Encoding {
+    Utf8,   // invalid sequences are shown as \xNN
+    Latin1, // every byte is a character of ISO-8859-1
+    Ascii,  // bytes other than printable ASCII are shown as \xNN
+}
+
+impl Encoding {
+    /// Get encoding from id of item in combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "utf8" => Some(Encoding::Utf8),
+            "latin1" => Some(Encoding::Latin1),
+            "ascii" => Some(Encoding::Ascii),
+            _ => None,
+        }
+    }
+
+    /// Text of bytes, nothing is dropped
+    pub(crate) fn decode(&self, bytes: &[u8]) -> String {
+        let mut text = String::with_capacity(bytes.len());
+        match self {
+            Encoding::Utf8 => {
+                let mut rest = bytes;
+                loop {
+                    match std::str::from_utf8(rest) {
+                        Ok(valid) => {
+                            text.push_str(valid);
+                            break;
+                        }
+                        Err(e) => {
+                            let (valid, after) = rest.split_at(e.valid_up_to());
+                            text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                            // sequence cut at end is invalid too, as line is complete
+                            let bad = e.error_len().unwrap_or(after.len());
+                            for byte in &after[..bad] {
+                                text.push_str(&format!("\\x{:02X}", byte));
+                            }
+                            rest = &after[bad..];
+                        }
+                    }
+                }
+            }
+            Encoding::Latin1 => text.extend(bytes.iter().map(|byte| *byte as char)),
+            Encoding::Ascii => {
+                for byte in bytes {
+                    match byte {
+                        b' '..=b'~' | b'\t' => text.push(*byte as char),
+                        _ => text.push_str(&format!("\\x{:02X}", byte)),
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+/// What ends a line of text coming from serial port
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Terminator {
+    Lf,       // \n
+    Cr,       // \r
+    CrLf,     // \r\n
+    Byte(u8), // any byte, like `>` of a prompt
+}
+
+impl Terminator {
+    /// Get terminator from id of item in combo box, byte is used for `byte`
+    pub(crate) fn from_id(id: &str, byte: u8) -> Option<Self> {
+        match id {
+            "lf" => Some(Terminator::Lf),
+            "cr" => Some(Terminator::Cr),
+            "crlf" => Some(Terminator::CrLf),
+            "byte" => Some(Terminator::Byte(byte)),
+            _ => None,
+        }
+    }
+
+    /// Parse byte written as `0x3E`, `\r`, `\n`, `\t` or a single ASCII character
+    pub(crate) fn parse_byte(text: &str) -> Option<u8> {
+        match text {
+            "\\r" => Some(b'\r'),
+            "\\n" => Some(b'\n'),
+            "\\t" => Some(b'\t'),
+            _ if text.starts_with("0x") || text.starts_with("0X") => {
+                u8::from_str_radix(&text[2..], 16).ok()
+            }
+            _ if text.len() == 1 => Some(text.as_bytes()[0]),
+            _ => None,
+        }
+    }
+
+    /// End of first line in bytes and where next line starts
+    pub(crate) fn find(&self, bytes: &[u8]) -> Option<(usize, usize)> {
+        match self {
+            Terminator::Lf => bytes.iter().position(|b| *b == b'\n').map(|p| (p, p + 1)),
+            Terminator::Cr => bytes.iter().position(|b| *b == b'\r').map(|p| (p, p + 1)),
+            Terminator::CrLf => bytes
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .map(|p| (p, p + 2)),
+            Terminator::Byte(byte) => bytes.iter().position(|b| b == byte).map(|p| (p, p + 1)),
+        }
+    }
+}
+
+/// Parse names of senders written like `192.168.1.20=kitchen, 192.168.1.21=garage`
+pub(crate) fn parse_aliases(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut aliases = HashMap::new();
+    for item in text.split(',') {
+        if item.trim().is_empty() {
+            continue;
+        }
+        match item.split_once('=') {
+            Some((address, name)) if !address.trim().is_empty() && !name.trim().is_empty() => {
+                aliases.insert(address.trim().to_owned(), name.trim().to_owned());
+            }
+            _ => return Err(format!("{} is not like 192.168.1.20=kitchen", item.trim())),
+        }
+    }
+    Ok(aliases)
+}
+
+/// Settings of serial line other than baud rate, like 8N1 or 7E1
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LineSettings {
+    pub(crate) data_bits: serialport::DataBits,
+    pub(crate) parity: serialport::Parity,
+    pub(crate) stop_bits: serialport::StopBits,
+    pub(crate) flow_control: serialport::FlowControl,
+}
+
+impl LineSettings {
+    pub(crate) fn default() -> Self {
+        LineSettings {
+            data_bits: serialport::DataBits::Eight,
+            parity: serialport::Parity::None,
+            stop_bits: serialport::StopBits::One,
+            flow_control: serialport::FlowControl::None,
+        }
+    }
+
+    /// Set data bits from id of item in combo box, false if id is unknown
+    pub(crate) fn set_data_bits(&mut self, id: &str) -> bool {
+        self.data_bits = match id {
+            "5" => serialport::DataBits::Five,
+            "6" => serialport::DataBits::Six,
+            "7" => serialport::DataBits::Seven,
+            "8" => serialport::DataBits::Eight,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Set parity from id of item in combo box, false if id is unknown
+    pub(crate) fn set_parity(&mut self, id: &str) -> bool {
+        self.parity = match id {
+            "none" => serialport::Parity::None,
+            "odd" => serialport::Parity::Odd,
+            "even" => serialport::Parity::Even,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Set stop bits from id of item in combo box, false if id is unknown
+    pub(crate) fn set_stop_bits(&mut self, id: &str) -> bool {
+        self.stop_bits = match id {
+            "1" => serialport::StopBits::One,
+            "2" => serialport::StopBits::Two,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Set flow control from id of item in combo box, false if id is unknown
+    pub(crate) fn set_flow_control(&mut self, id: &str) -> bool {
+        self.flow_control = match id {
+            "none" => serialport::FlowControl::None,
+            "hardware" => serialport::FlowControl::Hardware,
+            "software" => serialport::FlowControl::Software,
+            _ => return false,
+        };
+        true
+    }
+}
+
+/// Pulses on DTR and RTS which reset board when port is opened
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ResetOnConnect {
+    None,
+    Arduino, // DTR and RTS low then high, auto reset of Arduino boards
+    Esp32,   // EN pulled low by RTS while IO0 is high, normal boot of ESP32 and ESP8266
+}
+
+impl ResetOnConnect {
+    /// Get reset from id of item in combo box
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "none" => Some(ResetOnConnect::None),
+            "arduino" => Some(ResetOnConnect::Arduino),
+            "esp32" => Some(ResetOnConnect::Esp32),
+            _ => None,
+        }
+    }
+}
+
+/// Commands from window for "Thread to manage Serial Port", which owns the port
+#[derive(Debug)]
+pub(crate) enum PortCommand {
+    SetDtr(bool),
+    SetRts(bool),
+    Break(u32), // milliseconds
+    Write(Vec<u8>),
+}
+
+/// Levels of input lines of port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ModemLines {
+    pub(crate) cts: bool,
+    pub(crate) dsr: bool,
+    pub(crate) ri: bool,
+    pub(crate) cd: bool,
 }
 
 /// For communication between mpsc of graph and serial port
@@ -40,8 +342,20 @@ pub(crate) struct Properties {
 #[derive(Debug)]
 pub(crate) enum MessageSerialThread {
     Msg(String, MessageSerialThreadMsgType),
-    Points(Vec<(String, f64)>),
+    Points(Option<f64>, Vec<(String, f64)>), // value of pankti if not from counter, points
     Status(String),
+    ParseError(ParseError),
+    ModemLines(ModemLines),
+    BaudDetected(u32),
+    Marker(Option<f64>, String), // value of pankti if not from counter, text like "disconnected"
+}
+
+/// Token of a line which could not be read, rest of the line is still drawn
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    pub(crate) line: String,
+    pub(crate) column: usize, // in characters, starting from 1
+    pub(crate) reason: String,
 }
 
 #[derive(Debug)]
@@ -53,9 +367,132 @@ pub(crate) enum MessageSerialThreadMsgType {
 impl Properties {
     pub(crate) fn default() -> Self {
         Properties {
+            name: String::new(),
+            closed: AtomicBool::new(false),
+            started: Arc::new(Mutex::new(SystemTime::now())),
             bondrate: AtomicU32::new(9600),
             port: Mutex::new(String::new()),
             status: Mutex::new(Status::AVRODTIH),
+            line: Mutex::new(LineSettings::default()),
+            dtr: AtomicBool::new(true),
+            rts: AtomicBool::new(true),
+            reset_on_connect: Mutex::new(ResetOnConnect::None),
+            break_ms: AtomicU32::new(250),
+            auto_reconnect: AtomicBool::new(false),
+            decoder: Mutex::new(DecoderKind::Tarangam),
+            csv_delimiter: Mutex::new(','),
+            binary_format: Mutex::new(BinaryFormat::default()),
+            x_axis: Mutex::new(XAxis::Sankhya),
+            rules: Mutex::new(Vec::new()),
+            encoding: Mutex::new(Encoding::Utf8),
+            terminator: Mutex::new(Terminator::Lf),
+            idle_flush: AtomicU32::new(0),
+            aliases: Mutex::new(HashMap::new()),
+            mqtt_topics: Mutex::new(vec!["#".to_owned()]),
         }
     }
+
+    /// Copy of settings for another connection with name, start of session stays shared
+    pub(crate) fn duplicate(&self, name: &str) -> Self {
+        let props = Properties {
+            name: name.to_owned(),
+            started: Arc::clone(&self.started),
+            status: Mutex::new(Status::AVRODTIH),
+            ..Properties::default()
+        };
+        props
+            .bondrate
+            .store(self.bondrate.load(Ordering::SeqCst), Ordering::SeqCst);
+        copy(&props.port, &self.port);
+        props.copy_settings(self);
+        props
+    }
+
+    /// Take settings of Settings window from other connection, keeping own port and baud rate
+    pub(crate) fn copy_settings(&self, from: &Properties) {
+        copy(&self.line, &from.line);
+        self.dtr
+            .store(from.dtr.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.rts
+            .store(from.rts.load(Ordering::SeqCst), Ordering::SeqCst);
+        copy(&self.reset_on_connect, &from.reset_on_connect);
+        self.break_ms
+            .store(from.break_ms.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.auto_reconnect
+            .store(from.auto_reconnect.load(Ordering::SeqCst), Ordering::SeqCst);
+        copy(&self.decoder, &from.decoder);
+        copy(&self.csv_delimiter, &from.csv_delimiter);
+        copy(&self.binary_format, &from.binary_format);
+        copy(&self.x_axis, &from.x_axis);
+        copy(&self.rules, &from.rules);
+        copy(&self.encoding, &from.encoding);
+        copy(&self.terminator, &from.terminator);
+        self.idle_flush
+            .store(from.idle_flush.load(Ordering::SeqCst), Ordering::SeqCst);
+        copy(&self.aliases, &from.aliases);
+        copy(&self.mqtt_topics, &from.mqtt_topics);
+    }
+}
+
+/// Copy value of a setting, setting is left as it is if a lock fails
+fn copy<T: Clone>(to: &Mutex<T>, from: &Mutex<T>) {
+    if let (Ok(mut to), Ok(from)) = (to.lock(), from.lock()) {
+        *to = from.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_keeps_bad_bytes_as_hex() {
+        assert_eq!(Encoding::Utf8.decode("aé".as_bytes()), "aé");
+        assert_eq!(Encoding::Utf8.decode(b"a\xFFb"), "a\\xFFb");
+        // sequence cut at end of line
+        assert_eq!(Encoding::Utf8.decode(b"a\xC3"), "a\\xC3");
+    }
+
+    #[test]
+    fn terminators() {
+        let bytes = b"a\rb\r\nc>";
+        assert_eq!(Terminator::Lf.find(bytes), Some((4, 5)));
+        assert_eq!(Terminator::Cr.find(bytes), Some((1, 2)));
+        assert_eq!(Terminator::CrLf.find(bytes), Some((3, 5)));
+        assert_eq!(Terminator::Byte(b'>').find(bytes), Some((6, 7)));
+        assert_eq!(Terminator::CrLf.find(b"a\r"), None);
+    }
+
+    #[test]
+    fn terminator_byte() {
+        assert_eq!(Terminator::parse_byte("0x3E"), Some(b'>'));
+        assert_eq!(Terminator::parse_byte("0X0a"), Some(b'\n'));
+        assert_eq!(Terminator::parse_byte("\\r"), Some(b'\r'));
+        assert_eq!(Terminator::parse_byte(";"), Some(b';'));
+        assert_eq!(Terminator::parse_byte("0x"), None);
+        assert_eq!(Terminator::parse_byte("ab"), None);
+        assert_eq!(Terminator::parse_byte("é"), None);
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = parse_aliases(" 192.168.1.20=kitchen, 192.168.1.21 = garage ,").unwrap();
+        assert_eq!(
+            aliases.get("192.168.1.20").map(|a| a.as_str()),
+            Some("kitchen")
+        );
+        assert_eq!(
+            aliases.get("192.168.1.21").map(|a| a.as_str()),
+            Some("garage")
+        );
+        assert!(parse_aliases("").unwrap().is_empty());
+        assert!(parse_aliases("192.168.1.20").is_err());
+        assert!(parse_aliases("=kitchen").is_err());
+    }
+
+    #[test]
+    fn latin1_and_ascii() {
+        assert_eq!(Encoding::Latin1.decode(b"caf\xE9"), "café");
+        assert_eq!(Encoding::Ascii.decode(b"a\tb\x01\xE9"), "a\tb\\x01\\xE9");
+    }
 }